@@ -55,8 +55,15 @@ function showNotification(message) {
     }, 1000);
 }
 
+// 服务端生成的分享链接，反向代理或子路径部署时比 window.location 可靠
+var shareUrl = null;
+fetch(window.location.pathname.replace(/\/$/, '') + '/share')
+    .then(function (r) { return r.ok ? r.json() : null; })
+    .then(function (data) { if (data && data.url) shareUrl = data.url; })
+    .catch(function () {});
+
 function getUrl(url) {
-    var url = window.location.href;
+    var url = shareUrl || window.location.href;
     if (document.getElementById("markdown-content").style.display !== "none") {
         if (!url.includes('?marked')) {
             url = url + '?marked';
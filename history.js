@@ -21,10 +21,11 @@ function updateSidebar() {
     const historyList = document.getElementById('history-list');
     historyList.innerHTML = '';
     
+    const base = (window.NOTE_CONFIG && NOTE_CONFIG.base) || '';
     history.forEach((note, index) => {
         const li = document.createElement('li');
         li.innerHTML = `
-            <a href="/${note}">${note}</a>
+            <a href="${base}/${note}">${note}</a>
             <button class="delete-btn" data-index="${index}">×</button>
         `;
         historyList.appendChild(li);
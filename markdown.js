@@ -2,7 +2,8 @@ function autoMark() {
     const currentURL = window.location.href;
     const regex = /[?&]marked(?:=([^&#]*)|&|#|$)/i;
     const match = regex.exec(currentURL);
-    if (match !== null) {
+    // 服务端判断内容像 markdown 时（AUTO_RENDER_MARKDOWN）默认显示渲染结果
+    if (match !== null || (window.NOTE_CONFIG && NOTE_CONFIG.render)) {
         renderMarkdown();
     }
 }
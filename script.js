@@ -1,5 +1,11 @@
 function uploadContent() {
 
+    // Only part of a large note is shown until the full content is loaded, never save it.
+    if (window.NOTE_CONFIG && NOTE_CONFIG.truncated) {
+        setTimeout(uploadContent, 1000);
+        return;
+    }
+
     // If textarea value changes.
     if (content !== textarea.value) {
         var temp = textarea.value;
@@ -9,6 +15,18 @@ function uploadContent() {
         request.setRequestHeader('Content-Type', 'application/x-www-form-urlencoded; charset=UTF-8');
         request.onload = function() {
             if (request.readyState === 4) {
+                if (request.status >= 400) {
+
+                    // Rejected by the server, tell the user and try again later.
+                    showNotification(saveErrorMessage(request));
+                    setTimeout(uploadContent, 5000);
+                    return;
+                }
+
+                // The server cut the content to the size limit (SIZE_LIMIT_BEHAVIOR=truncate).
+                if (request.getResponseHeader('X-Note-Truncated')) {
+                    showNotification('saved, but truncated to the size limit');
+                }
 
                 // Request has ended, check again after 1 second.
                 content = temp;
@@ -33,6 +51,18 @@ function uploadContent() {
     }
 }
 
+function saveErrorMessage(request) {
+    var err = null;
+    try { err = JSON.parse(request.responseText); } catch (e) {}
+    if (err && err.error === 'file_limit_reached') {
+        return 'not saved: file limit reached (' + err.count + '/' + err.limit + ')';
+    }
+    if (err && err.error === 'note_id_too_short') {
+        return 'not saved: note ID must be at least ' + err.min_length + ' characters';
+    }
+    return 'not saved (' + request.status + ')';
+}
+
 var textarea = document.getElementById('content');
 var printable = document.getElementById('printable');
 var content = textarea.value;
@@ -73,8 +103,9 @@ Mousetrap.bind('mod+k', function () {
 
 function handlePaste(e) {
     const items = e.clipboardData.items;
+    const uploads = !(window.NOTE_CONFIG && !NOTE_CONFIG.uploads);
     
-    for (let i = 0; i < items.length; i++) {
+    for (let i = 0; uploads && i < items.length; i++) {
         if (items[i].type.indexOf('image') !== -1) {
             e.preventDefault();
             const blob = items[i].getAsFile();
@@ -85,18 +116,51 @@ function handlePaste(e) {
             break;
         }
     }
+
+    // 大段文本粘贴前先让服务端确认能否保存，不能保存时提示原因
+    const text = e.clipboardData.getData('text');
+    if (text.length > 64 * 1024) {
+        checkBeforeSave(textarea.value + text);
+    }
+}
+
+function checkBeforeSave(text) {
+    fetch(window.location.pathname.replace(/\/$/, '') + '/check', {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/x-www-form-urlencoded; charset=UTF-8' },
+        body: 'text=' + encodeURIComponent(text)
+    })
+    .then(r => r.ok ? r.json() : null)
+    .then(data => {
+        if (!data || data.ok) return;
+        switch (data.reason) {
+            case 'too_large':
+                showNotification('too large to save (max ' + Math.floor(data.limit / 1024) + 'KB)');
+                break;
+            case 'file_limit_reached':
+                showNotification('not saved: file limit reached (' + data.count + '/' + data.limit + ')');
+                break;
+            default:
+                showNotification('not saved: ' + data.reason);
+        }
+    })
+    .catch(() => {});
 }
 
 function uploadImage(blob) {
     const formData = new FormData();
     formData.append('file', blob, 'clipboard_image.png');
 
-    fetch('/upload', {
+    fetch(NOTE_CONFIG.base + '/upload', {
         method: 'POST',
         body: formData
     })
-    .then(response => {
-        if (!response.ok) throw new Error('upload failed');
+    .then(async response => {
+        if (!response.ok) {
+            const err = await response.json().catch(() => null);
+            showNotification(uploadErrorMessage(err));
+            throw new Error('upload failed');
+        }
         return response.json();
     })
     .then(data => {
@@ -114,13 +178,38 @@ function uploadImage(blob) {
     });
 }
 
+// 根据服务端返回的 reason 给出具体的上传失败提示
+function uploadErrorMessage(err) {
+    if (!err || !err.error) return 'upload failed';
+    switch (err.error) {
+        case 'file_too_large':
+            return 'file too large (max ' + Math.floor(err.limit / 1024 / 1024) + 'MB)';
+        case 'file_limit_reached':
+            return 'file limit reached (' + err.limit + ' files)';
+        default:
+            return err.message || 'upload failed';
+    }
+}
+
 function insertImageUrl(url) {
     const textarea = document.getElementById('content');
     const cursorPos = textarea.selectionStart;
     const textBefore = textarea.value.substring(0, cursorPos);
     const textAfter = textarea.value.substring(cursorPos);
+    const snippet = uploadSnippet(url, '', true);
     
-    textarea.value = textBefore + `![](${url})` + textAfter;
-    textarea.selectionStart = textarea.selectionEnd = cursorPos + url.length + 4;
+    textarea.value = textBefore + snippet + textAfter;
+    textarea.selectionStart = textarea.selectionEnd = cursorPos + snippet.length;
     textarea.focus();
 }
+
+// 上传成功后插入的片段，格式由服务端 UPLOAD_INSERT_FORMAT 决定（markdown 或 html）
+function uploadSnippet(url, name, isImage) {
+    if (window.NOTE_CONFIG && NOTE_CONFIG.insert_format === 'html') {
+        const esc = s => String(s).replace(/&/g, '&amp;').replace(/"/g, '&quot;').replace(/</g, '&lt;').replace(/>/g, '&gt;');
+        return isImage
+            ? `<img src="${esc(url)}" alt="${esc(name)}">`
+            : `<a href="${esc(url)}">${esc(name || 'attachment')}</a>`;
+    }
+    return isImage ? `![](${url})` : `[${name || 'attachment'}](${url})`;
+}
@@ -0,0 +1,85 @@
+use axum::extract::{ConnectInfo, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use hyper::body::Body as _;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::Arc;
+use tracing::error;
+
+// NCSA Combined Log Format 访问日志，与 tracing 日志分开写入 ACCESS_LOG_PATH。
+// 请求线程只负责把格式化好的行发送到通道，由独立线程追加写文件
+pub struct AccessLog {
+    tx: mpsc::Sender<String>,
+}
+
+impl AccessLog {
+    pub fn open(path: &Path) -> io::Result<Arc<AccessLog>> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (tx, rx) = mpsc::channel::<String>();
+        std::thread::spawn(move || {
+            for line in rx {
+                if let Err(e) = file.write_all(line.as_bytes()) {
+                    error!("access log write error: {e}");
+                }
+            }
+        });
+        Ok(Arc::new(AccessLog { tx }))
+    }
+}
+
+pub async fn middleware(log: Arc<AccessLog>, req: Request, next: Next) -> Response {
+    let client = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+        .unwrap_or_else(|| "-".to_string());
+    let request_line = escape(&format!(
+        "{} {} {:?}",
+        req.method(),
+        req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/"),
+        req.version()
+    ));
+    let referer = header(&req, "referer");
+    let ua = header(&req, "user-agent");
+
+    let resp = next.run(req).await;
+
+    // 流式响应长度未知时记为 "-"
+    let bytes = resp
+        .body()
+        .size_hint()
+        .exact()
+        .map(|n| n.to_string())
+        .unwrap_or_else(|| "-".to_string());
+    let line = format!(
+        "{client} - - [{time}] \"{request_line}\" {status} {bytes} \"{referer}\" \"{ua}\"\n",
+        time = chrono::Local::now().format("%d/%b/%Y:%H:%M:%S %z"),
+        status = resp.status().as_u16(),
+    );
+    let _ = log.tx.send(line);
+    resp
+}
+
+fn header(req: &Request, name: &str) -> String {
+    escape(req.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or("-"))
+}
+
+// 转义引号与控制字符，避免客户端伪造日志行
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => out.push('?'),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// note 最近一次被读取的时间（秒）。读取时只更新内存，由后台任务定期写入
+// save_path/.meta/_access.json，避免每次读取都写盘；进程异常退出时最多丢失一个周期的记录
+pub struct AccessTimes {
+    path: PathBuf,
+    times: Mutex<HashMap<String, u64>>,
+    dirty: AtomicBool,
+}
+
+impl AccessTimes {
+    // 文件不存在或损坏时从空记录开始
+    pub fn load(save_path: &Path) -> AccessTimes {
+        let path = save_path.join(crate::meta::META_DIR).join("_access.json");
+        let times = fs::read(&path)
+            .ok()
+            .and_then(|b| serde_json::from_slice(&b).ok())
+            .unwrap_or_default();
+        AccessTimes {
+            path,
+            times: Mutex::new(times),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn touch(&self, note: &str) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        self.times.lock().unwrap().insert(note.to_string(), now);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, note: &str) -> Option<u64> {
+        self.times.lock().unwrap().get(note).copied()
+    }
+
+    // note 改名后读取时间跟随到新 ID
+    pub fn rename(&self, from: &str, to: &str) {
+        let mut times = self.times.lock().unwrap();
+        if let Some(time) = times.remove(from) {
+            times.insert(to.to_string(), time);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // 有变化时写盘，顺带清掉已不存在的 note；先写临时文件再改名，避免留下半个文件
+    pub fn flush(&self, save_path: &Path) -> io::Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let data = {
+            let mut times = self.times.lock().unwrap();
+            times.retain(|note, _| save_path.join(note).is_file());
+            serde_json::to_vec(&*times)?
+        };
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
@@ -0,0 +1,52 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::error;
+
+// note 内容变更的审计日志（AUDIT_LOG_PATH），每行一个 JSON 事件，只追加不改写。
+// 与访问日志不同，这里在请求内同步写入，响应返回时事件已写到文件
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> io::Result<Arc<AuditLog>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Arc::new(AuditLog { file: Mutex::new(file) }))
+    }
+
+    // event: create / update / delete / reap（改名见 record_rename）；bytes 为写入后（删除时为删除前）的磁盘大小。
+    // ip 为 None 表示由后台任务（闲置清理）触发，记为 null
+    pub fn record(&self, event: &str, note: &str, ip: Option<IpAddr>, bytes: u64) {
+        self.write(serde_json::json!({
+            "time": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "event": event,
+            "note": note,
+            "ip": ip.map(|ip| ip.to_string()),
+            "bytes": bytes,
+        }));
+    }
+
+    // 改名事件：note 为原 ID，to 为新 ID
+    pub fn record_rename(&self, from: &str, to: &str, ip: IpAddr, bytes: u64) {
+        self.write(serde_json::json!({
+            "time": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "event": "rename",
+            "note": from,
+            "to": to,
+            "ip": ip.to_string(),
+            "bytes": bytes,
+        }));
+    }
+
+    fn write(&self, event: serde_json::Value) {
+        let mut line = event.to_string();
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+            error!("audit log write error: {e}");
+        }
+    }
+}
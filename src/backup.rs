@@ -0,0 +1,82 @@
+use crate::meta::META_DIR;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::error;
+
+// BACKUP_PATH：把 note 与上传文件的写入/删除镜像到另一个目录（通常在另一块磁盘上）。
+// 镜像在阻塞线程池中异步完成，失败只记日志，不影响主目录的写入
+pub struct Backup {
+    root: PathBuf,
+    save_path: Arc<PathBuf>,
+    // 串行化镜像操作：后执行的任务总是读取主目录的最新状态，不会被先发起的任务覆盖
+    lock: Mutex<()>,
+}
+
+impl Backup {
+    pub fn new(root: PathBuf, save_path: Arc<PathBuf>) -> io::Result<Arc<Backup>> {
+        fs::create_dir_all(root.join(META_DIR))?;
+        Ok(Arc::new(Backup { root, save_path, lock: Mutex::new(()) }))
+    }
+
+    // 按主目录现状同步一个 note 或上传文件，连同其 .meta 下的附加信息：存在则复制，不存在则删除
+    pub fn mirror(self: &Arc<Self>, name: &str) {
+        let backup = self.clone();
+        let name = name.to_string();
+        tokio::task::spawn_blocking(move || {
+            let _guard = backup.lock.lock().unwrap_or_else(|e| e.into_inner());
+            let meta = Path::new(META_DIR);
+            for rel in [PathBuf::from(&name), meta.join(format!("{name}.json")), meta.join("_tmp").join(&name)] {
+                if let Err(e) = backup.sync_file(&rel) {
+                    error!("backup mirror {rel:?} error: {e}");
+                }
+            }
+        });
+    }
+
+    // 启动时的全量同步：复制主目录中的所有文件（不含分块上传的临时文件），返回复制的文件数。
+    // 备份目录中多出的文件不删除
+    pub fn full_sync(&self) -> io::Result<usize> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let meta = Path::new(META_DIR);
+        let mut copied = 0;
+        for dir in [PathBuf::new(), meta.to_path_buf(), meta.join("_tmp")] {
+            let entries = match fs::read_dir(self.save_path.join(&dir)) {
+                Ok(rd) => rd,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for entry in entries.flatten() {
+                if !entry.file_type().is_ok_and(|t| t.is_file()) {
+                    continue;
+                }
+                let rel = dir.join(entry.file_name());
+                match self.sync_file(&rel) {
+                    Ok(()) => copied += 1,
+                    Err(e) => error!("backup sync {rel:?} error: {e}"),
+                }
+            }
+        }
+        Ok(copied)
+    }
+
+    fn sync_file(&self, rel: &Path) -> io::Result<()> {
+        let src = self.save_path.join(rel);
+        let dst = self.root.join(rel);
+        if !src.is_file() {
+            return match fs::remove_file(&dst) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        if let Some(dir) = dst.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // 先写临时文件再改名，备份中不会出现写了一半的文件
+        let mut tmp = dst.as_os_str().to_owned();
+        tmp.push(".backup-tmp");
+        fs::copy(&src, &tmp)?;
+        fs::rename(&tmp, &dst)
+    }
+}
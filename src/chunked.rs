@@ -0,0 +1,155 @@
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// 分块上传（tus 协议的简化子集）：init 登记总大小，按偏移顺序追加分块，complete 时整体转存。
+// 未完成的数据写在 save_path/.meta/_partial/<id>；登记信息只在内存中，重启后旧的分块作废。
+// pending 锁只在查找、更新登记信息时持有，读写磁盘都在锁外，各个上传互不阻塞
+pub struct ChunkedUploads {
+    dir: PathBuf,
+    ttl: Duration,
+    max_pending: usize,
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+struct Pending {
+    file_name: String,
+    total: usize,
+    received: usize,
+    last_active: Instant,
+    // 正在锁外写入一个分块；期间同一上传的其他分块按偏移不符拒绝
+    writing: bool,
+}
+
+#[derive(Debug)]
+pub enum ChunkError {
+    NotFound,
+    // 偏移与已接收的字节数不一致，携带服务端当前偏移供客户端续传
+    OffsetMismatch(usize),
+    TooLarge,
+    Incomplete(usize),
+    TooManyPending,
+    Io(io::Error),
+}
+
+impl From<io::Error> for ChunkError {
+    fn from(e: io::Error) -> Self {
+        ChunkError::Io(e)
+    }
+}
+
+impl ChunkedUploads {
+    pub fn new(save_path: &Path, ttl: Duration, max_pending: usize) -> ChunkedUploads {
+        let dir = save_path.join(crate::meta::META_DIR).join("_partial");
+        let _ = fs::remove_dir_all(&dir);
+        ChunkedUploads {
+            dir,
+            ttl,
+            max_pending,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn part_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    // 超过 ttl 没有新分块的上传视为放弃，返回需要删除分块数据的 ID（在锁外删除）
+    fn prune(&self, pending: &mut HashMap<String, Pending>) -> Vec<String> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        pending.retain(|id, p| {
+            let alive = p.writing || now.duration_since(p.last_active) < self.ttl;
+            if !alive {
+                expired.push(id.clone());
+            }
+            alive
+        });
+        expired
+    }
+
+    pub fn init(&self, file_name: String, total: usize) -> Result<String, ChunkError> {
+        let id: String = rand::thread_rng().sample_iter(&Alphanumeric).take(24).map(char::from).collect();
+        let expired = {
+            let mut pending = self.pending.lock().unwrap();
+            let expired = self.prune(&mut pending);
+            if pending.len() >= self.max_pending {
+                return Err(ChunkError::TooManyPending);
+            }
+            pending.insert(
+                id.clone(),
+                Pending {
+                    file_name,
+                    total,
+                    received: 0,
+                    last_active: Instant::now(),
+                    writing: false,
+                },
+            );
+            expired
+        };
+        for old in expired {
+            let _ = fs::remove_file(self.part_path(&old));
+        }
+        let created = fs::create_dir_all(&self.dir).and_then(|_| fs::File::create(self.part_path(&id)));
+        if let Err(e) = created {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e.into());
+        }
+        Ok(id)
+    }
+
+    // 返回 (已接收字节数, 总大小)
+    pub fn status(&self, id: &str) -> Option<(usize, usize)> {
+        let pending = self.pending.lock().unwrap();
+        pending.get(id).map(|p| (p.received, p.total))
+    }
+
+    // 阻塞的磁盘写入，应在 spawn_blocking 中调用
+    pub fn append(&self, id: &str, offset: usize, data: &[u8]) -> Result<usize, ChunkError> {
+        {
+            let mut pending = self.pending.lock().unwrap();
+            let p = pending.get_mut(id).ok_or(ChunkError::NotFound)?;
+            if p.writing || offset != p.received {
+                return Err(ChunkError::OffsetMismatch(p.received));
+            }
+            if p.received + data.len() > p.total {
+                return Err(ChunkError::TooLarge);
+            }
+            p.writing = true;
+            p.last_active = Instant::now();
+        }
+        let written = write_chunk(&self.part_path(id), offset, data);
+        let mut pending = self.pending.lock().unwrap();
+        let p = pending.get_mut(id).ok_or(ChunkError::NotFound)?;
+        p.writing = false;
+        written?;
+        p.received += data.len();
+        p.last_active = Instant::now();
+        Ok(p.received)
+    }
+
+    // 全部分块到齐后移除登记，返回文件名与拼好的临时文件路径；调用方负责把文件移走或删除
+    pub fn finish(&self, id: &str) -> Result<(String, PathBuf), ChunkError> {
+        let mut pending = self.pending.lock().unwrap();
+        let p = pending.get(id).ok_or(ChunkError::NotFound)?;
+        if p.writing || p.received != p.total {
+            return Err(ChunkError::Incomplete(p.received));
+        }
+        let p = pending.remove(id).ok_or(ChunkError::NotFound)?;
+        Ok((p.file_name, self.part_path(id)))
+    }
+}
+
+// 从 offset 处写入分块；之前失败的写入可能留下半截数据，先截断到 offset
+fn write_chunk(path: &Path, offset: usize, data: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(offset as u64)?;
+    file.seek(SeekFrom::Start(offset as u64))?;
+    file.write_all(data)
+}
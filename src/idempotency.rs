@@ -0,0 +1,45 @@
+use axum::http::StatusCode;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// 按 Idempotency-Key 记录已完成请求的结果，重试时直接返回原结果。
+// 只保存在内存中，条目超过 ttl 或总数超过 max 时淘汰最早的
+pub struct IdempotencyCache {
+    ttl: Duration,
+    max: usize,
+    entries: Mutex<HashMap<String, (Instant, StatusCode, serde_json::Value)>>,
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration, max: usize) -> IdempotencyCache {
+        IdempotencyCache {
+            ttl,
+            max,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 1~128 个可见 ASCII 字符
+    pub fn valid_key(key: &str) -> bool {
+        (1..=128).contains(&key.len()) && key.bytes().all(|b| b.is_ascii_graphic())
+    }
+
+    pub fn get(&self, key: &str) -> Option<(StatusCode, serde_json::Value)> {
+        let entries = self.entries.lock().unwrap();
+        let (at, status, value) = entries.get(key)?;
+        (at.elapsed() < self.ttl).then(|| (*status, value.clone()))
+    }
+
+    pub fn insert(&self, key: String, status: StatusCode, value: serde_json::Value) {
+        let mut entries = self.entries.lock().unwrap();
+        let ttl = self.ttl;
+        entries.retain(|_, (at, _, _)| at.elapsed() < ttl);
+        if entries.len() >= self.max {
+            if let Some(oldest) = entries.iter().min_by_key(|(_, (at, _, _))| *at).map(|(k, _)| k.clone()) {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key, (Instant::now(), status, value));
+    }
+}
@@ -1,31 +1,256 @@
-use axum::extract::{Multipart, Path, Query, State};
-use axum::http::{HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::body::Body;
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Multipart, Path, Query, State};
+use axum::http::header::CACHE_CONTROL;
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, Uri};
 use axum::response::{Html, IntoResponse, Redirect, Response};
 use axum::routing::{get, post};
-use axum::{Form, Router};
+use axum::body::Bytes;
+use axum::{Form, Json, Router};
 use once_cell::sync::Lazy;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use rand::Rng;
 use regex::Regex;
 use serde::Deserialize;
+use anyhow::Context;
+use flate2::read::GzDecoder;
+use std::borrow::Cow;
 use std::env;
 use std::fs;
-use std::io;
+use std::io::{self, Read};
+use std::net::SocketAddr;
 use std::path::{Path as FsPath, PathBuf};
 use std::sync::Arc;
-use tower_http::cors::CorsLayer;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer};
+use tower_http::set_header::SetResponseHeaderLayer;
 use tower_http::trace::TraceLayer;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 
+mod access_log;
+mod access_times;
+mod audit;
+mod backup;
+mod chunked;
+mod idempotency;
+mod markdown;
+mod meta;
+mod metrics;
+mod ratelimit;
+mod store;
+mod server;
+mod stats;
+
+use metrics::Metrics;
+use stats::FileStats;
+
 static NOTE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z0-9_-]{1,64}$").unwrap());
-static RANDOM_ALPHABET: &[u8] = b"234579abcdefghjkmnpqrstwxyz"; // 与 PHP 版本一致
+// 启动时检查的关键静态资源（相对 STATIC_ROOT）
+const KEY_ASSETS: &[&str] = &["styles.css", "script.js", "markdown.js", "public/js/marked.min.js"];
+// EDIT_TOKEN 对应的 Cookie 名
+const EDIT_COOKIE: &str = "edit_token";
+// 与单段固定路由同名、无法作为 note 访问的 ID
+const RESERVED_NOTE_IDS: &[&str] = &["upload", "healthz", "readyz", "ping", "metrics"];
+// STATIC_ROOT 根目录下可直接访问的文件扩展名；note ID 不含 "."，两者不会冲突
+const DEFAULT_STATIC_EXTENSIONS: &str = "css,js,svg,ico";
+static STATIC_NAME_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z0-9_-][a-zA-Z0-9_.-]*$").unwrap());
+// URL 路径段中需要编码的字符
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+// raw 输出可通过 ?mime= 选择的类型
+const RAW_MIME_ALLOWLIST: &[(&str, &str)] = &[
+    ("text/plain", "text/plain; charset=utf-8"),
+    ("text/markdown", "text/markdown; charset=utf-8"),
+    ("text/csv", "text/csv; charset=utf-8"),
+    ("application/json", "application/json"),
+];
+const DEFAULT_RANDOM_ALPHABET: &str = "234579abcdefghjkmnpqrstwxyz"; // 与 PHP 版本一致
+const RAW_STREAM_THRESHOLD: u64 = 1024 * 1024; // raw 输出超过 1MB 时改为流式
+const MAX_UPLOAD_SIZE: usize = 100 * 1024 * 1024; // 单个上传文件限制 100MB
+const DEFAULT_SANITIZE_CHARS: &str = "\\/:*?\"<>|"; // 上传文件名中默认替换的字符
+const MAX_BULK_DELETE: usize = 500; // 批量删除单次最多条数
+const MAX_BATCH_GET: usize = 100; // 批量读取单次最多条数
+const MAX_ARCHIVE_ENTRIES: usize = 1000; // /_tmp/:file/list 最多返回的条目数
+const MAX_DESCRIPTION_LENGTH: usize = 300; // 自定义页面描述的最大字符数
+const CHUNKED_UPLOAD_TTL: u64 = 24 * 3600; // 分块上传多久没有新分块视为放弃（秒）
+const MAX_PENDING_UPLOADS: usize = 100; // 同时进行中的分块上传数量上限
+const IDEMPOTENCY_TTL: u64 = 24 * 3600; // 上传 Idempotency-Key 的保留时间（秒）
+const MAX_IDEMPOTENCY_KEYS: usize = 10_000; // 内存中最多保留的 Idempotency-Key 数量
+const MAX_DECOMPRESSED_BODY: usize = 8 * 1024 * 1024; // gzip 请求体解压上限默认值，防止压缩炸弹
+
+// SEED_WELCOME_NOTE 在全新的 SAVE_PATH 中创建的说明页
+const WELCOME_NOTE_ID: &str = "welcome";
+const WELCOME_NOTE: &str = "# Welcome to web-mini-note
+
+- Every URL is a note: open `/anything` and start typing, changes are saved automatically.
+- Share a note by sharing its link; press `Ctrl/Cmd + E` to toggle the rendered markdown view.
+- Paste an image or use **upload** to attach files.
+- From a terminal: `curl -d text=hello https://<host>/mynote` to write, `curl https://<host>/mynote` to read.
+
+# 欢迎使用 web-mini-note
+
+- 每个地址就是一篇笔记：打开 `/任意名字` 直接输入，内容会自动保存。
+- 分享链接即可分享笔记；`Ctrl/Cmd + E` 切换 markdown 渲染视图。
+- 粘贴图片或点击 **upload** 上传附件。
+";
+
+// raw 输出末尾换行：原样输出 / 保证以单个换行结尾 / 去掉末尾换行
+#[derive(Clone, Copy)]
+enum RawNewline {
+    Verbatim,
+    Ensure,
+    Trim,
+}
+
+impl RawNewline {
+    fn parse(s: &str) -> Option<RawNewline> {
+        match s {
+            "verbatim" => Some(RawNewline::Verbatim),
+            "nl" => Some(RawNewline::Ensure),
+            "trim" => Some(RawNewline::Trim),
+            _ => None,
+        }
+    }
+
+    fn apply(self, buf: &mut Vec<u8>) {
+        if matches!(self, RawNewline::Verbatim) || buf.is_empty() {
+            return;
+        }
+        while matches!(buf.last(), Some(b'\n' | b'\r')) {
+            buf.pop();
+        }
+        if matches!(self, RawNewline::Ensure) {
+            buf.push(b'\n');
+        }
+    }
+}
+
+// 上传成功后编辑器插入的片段格式（UPLOAD_INSERT_FORMAT）
+#[derive(Clone, Copy)]
+enum InsertFormat {
+    Markdown,
+    Html,
+}
+
+impl InsertFormat {
+    fn as_str(self) -> &'static str {
+        match self {
+            InsertFormat::Markdown => "markdown",
+            InsertFormat::Html => "html",
+        }
+    }
+}
+
+// 访问 / 时的行为
+#[derive(Clone)]
+enum RootBehavior {
+    Random,
+    Landing,
+    Fixed(String),
+}
 
 #[derive(Clone)]
 struct AppState {
     save_path: Arc<PathBuf>,
     file_limit: usize,
     single_file_size_limit: usize,
+    // SIZE_LIMIT_BEHAVIOR=truncate：超出上限的内容截断到上限后保存，而不是拒绝
+    truncate_oversize: bool,
     static_root: Arc<PathBuf>,
+    static_extensions: Arc<Vec<String>>,
+    strict_note_ids: bool,
+    // 只允许写入已存在或长度不小于 NOTE_ID_LENGTH 的 note，避免短 ID 空间被随手占用
+    reject_short_ids: bool,
+    // STRICT_QUERY：note 页面遇到未知查询参数时返回 400
+    strict_query: bool,
+    listing_enabled: bool,
+    max_list_results: usize,
+    // 页面 meta description 与列表/搜索摘要各自的长度（字符数）
+    excerpt_length: usize,
+    snippet_length: usize,
+    stats: Arc<FileStats>,
+    // TRACK_ACCESS_TIME：记录 note 最近读取时间，列表可按其排序
+    access_times: Option<Arc<access_times::AccessTimes>>,
+    root_behavior: RootBehavior,
+    note_id_length: usize,
+    // RANDOM_ALPHABET：生成随机 note ID 使用的字符集
+    random_alphabet: Arc<Vec<u8>>,
+    note_id_retries: usize,
+    note_id_retry_warn: usize,
+    metrics: Arc<Metrics>,
+    uploads_enabled: bool,
+    embed_origins: Arc<String>,
+    sanitize: Arc<SanitizeOptions>,
+    admin_token: Option<Arc<String>>,
+    // EDIT_TOKEN：设置后只有带该令牌（Cookie 或 X-Edit-Token）的请求可以修改内容，其余访客只读
+    edit_token: Option<Arc<String>>,
+    note_size_hard_cap: usize,
+    // 设置了 TZ 或 TIME_FORMAT 时，API 额外返回按本地时区格式化的 modified_human
+    time_format: Option<Arc<String>>,
+    // 请求体（gzip 解压后）大小上限，解压过程中一旦超出即返回 413
+    max_decompressed_body: usize,
+    // COMPRESS_NOTES：note 以 gzip 压缩后写入磁盘
+    compress_notes: bool,
+    insert_format: InsertFormat,
+    // VERIFY_CONTENT_HASH：写入时记录内容哈希，读取时校验
+    verify_content: bool,
+    // AUTO_RENDER_MARKDOWN：内容像 markdown 的 note 打开时默认显示渲染结果
+    auto_render: bool,
+    // AUDIT_LOG_PATH：记录 note 创建/修改/删除事件
+    audit_log: Option<Arc<audit::AuditLog>>,
+    // BACKUP_PATH：note 与上传文件写入/删除后异步镜像到的备份目录
+    backup: Option<Arc<backup::Backup>>,
+    // EMPTY_POST_BEHAVIOR=clear：空内容保存为空文件而不是删除
+    empty_post_clears: bool,
+    chunked: Arc<chunked::ChunkedUploads>,
+    // /upload 的 Idempotency-Key 与对应的上传结果
+    upload_keys: Arc<idempotency::IdempotencyCache>,
+    // BASE_PATH，生成链接与跳转地址时作为前缀；根路径部署时为空串
+    base_path: Arc<String>,
+    // PUBLIC_URL：对外访问地址（含子路径），生成分享链接时使用；未设置时按请求头推断
+    public_url: Option<Arc<String>>,
+    // TRUST_PROXY_HEADERS：未设置 PUBLIC_URL 时是否采用 X-Forwarded-Proto/X-Forwarded-Host；
+    // 这两个头可由客户端任意伪造，只在确实部署于会覆盖它们的反向代理之后才开启
+    trust_proxy_headers: bool,
+    // ENABLE_PWA 开启时预先生成的 manifest.json 内容
+    pwa_manifest: Option<Arc<String>>,
+    // PINNED_NOTES：管理员置顶的 note ID，显示在侧边栏并由 /api/pinned 返回
+    pinned_notes: Arc<Vec<String>>,
+    // 超过该天数未修改（开启 TRACK_ACCESS_TIME 时也未被读取）的 note 会被定期删除；0 表示不清理
+    note_max_idle_days: u64,
+    // EDITOR_DISPLAY_LIMIT：编辑页内联的最大字节数，超出时只内联开头部分并只读，0 表示不限制
+    editor_display_limit: usize,
+    // TEMPLATES_DIR：note 模板目录，每个文件一个模板，文件名（去掉 .md/.txt）即模板名
+    templates_dir: Option<Arc<PathBuf>>,
+    // CUSTOM_CSS_PATH：追加在 styles.css 之后的样式覆盖文件，每次请求重新读取，修改后无需重启
+    custom_css: Option<Arc<PathBuf>>,
+    // SITE_NOTICE / SITE_NOTICE_PATH：页面顶部的公告，已转义（或按 SITE_NOTICE_HTML 原样）的 HTML
+    site_notice: Option<Arc<String>>,
+    // READ_RATE_LIMIT：每个 IP 每分钟读取 note 的次数上限
+    read_limiter: Option<Arc<ratelimit::RateLimiter>>,
+    // MAX_UPLOADS_PER_IP：每个 IP 同时进行中的 /upload 请求数上限
+    upload_slots: Option<Arc<ratelimit::ConcurrencyLimiter>>,
+    // MAX_FILE_SERVES：同时进行中的上传文件读取（/_tmp/...）总数上限，防止大量长连接耗尽文件描述符
+    file_slots: Option<Arc<ratelimit::FileSlots>>,
+    // raw 输出末尾换行的默认处理方式（RAW_NEWLINE），可被 ?raw=nl|trim 覆盖
+    raw_newline: RawNewline,
+    // _tmp 上传文件数量上限，超出时删除最早的上传；0 表示不限制
+    max_tmp_files: usize,
+    // LEGACY_CACHE_HEADERS：不缓存的响应是否附带 Pragma/Expires
+    legacy_cache_headers: bool,
+    // UPLOAD_NAME_LENGTH：上传响应 display_name（插入的链接文字）的最大字符数，0 表示不截断
+    upload_name_length: usize,
 }
 
 #[tokio::main]
@@ -39,261 +264,3165 @@ async fn main() -> anyhow::Result<()> {
         .ok()
         .and_then(|s| s.parse().ok())
         .unwrap_or(10240);
+    let truncate_oversize = match env::var("SIZE_LIMIT_BEHAVIOR").as_deref().map(str::trim) {
+        Ok("reject") | Ok("") | Err(_) => false,
+        Ok("truncate") => true,
+        Ok(other) => anyhow::bail!("invalid SIZE_LIMIT_BEHAVIOR {other:?}, expected reject or truncate"),
+    };
     let static_root = env::var("STATIC_ROOT").unwrap_or_else(|_| ".".to_string());
+    let mut static_extensions = Vec::new();
+    for ext in env::var("STATIC_EXTENSIONS")
+        .unwrap_or_else(|_| DEFAULT_STATIC_EXTENSIONS.to_string())
+        .split(',')
+        .map(|e| e.trim().trim_start_matches('.').to_ascii_lowercase())
+        .filter(|e| !e.is_empty())
+    {
+        if !ext.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            anyhow::bail!("invalid STATIC_EXTENSIONS entry {ext:?}: expected letters and digits");
+        }
+        if !static_extensions.contains(&ext) {
+            static_extensions.push(ext);
+        }
+    }
+    let strict_note_ids = env_flag("STRICT_NOTE_IDS", false);
+    let reject_short_ids = env_flag("REJECT_SHORT_NOTE_IDS", false);
+    let strict_query = env_flag("STRICT_QUERY", false);
+    let root_behavior = match env::var("ROOT_BEHAVIOR").unwrap_or_default().trim() {
+        "" | "random" => RootBehavior::Random,
+        "landing" => RootBehavior::Landing,
+        other => match other.strip_prefix("fixed:") {
+            Some(id) if NOTE_RE.is_match(id) => RootBehavior::Fixed(id.to_string()),
+            _ => anyhow::bail!("invalid ROOT_BEHAVIOR {other:?}, expected random, landing or fixed:<note>"),
+        },
+    };
+    let note_id_length: usize = env::var("NOTE_ID_LENGTH")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|&n| (1..=64).contains(&n))
+        .unwrap_or(5);
+    let note_id_retries: usize = env::var("NOTE_ID_RETRIES").ok().and_then(|s| s.parse().ok()).unwrap_or(10);
+    let note_id_retry_warn: usize = env::var("NOTE_ID_RETRY_WARN").ok().and_then(|s| s.parse().ok()).unwrap_or(3);
+    // 字符必须都能出现在 note ID 中；重复字符去掉，避免某些字符概率偏高
+    let mut random_alphabet: Vec<u8> = Vec::new();
+    for c in env::var("RANDOM_ALPHABET")
+        .ok()
+        .filter(|s| !s.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_RANDOM_ALPHABET.to_string())
+        .trim()
+        .chars()
+    {
+        if !(c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            anyhow::bail!("invalid character {c:?} in RANDOM_ALPHABET, expected [a-zA-Z0-9_-]");
+        }
+        if !random_alphabet.contains(&(c as u8)) {
+            random_alphabet.push(c as u8);
+        }
+    }
+    if random_alphabet.len() < 2 {
+        anyhow::bail!("RANDOM_ALPHABET needs at least 2 distinct characters");
+    }
+    let metrics_enabled = env_flag("METRICS_ENABLED", false);
+    // 写入/上传大小直方图的分桶上界（字节，逗号分隔）
+    let size_buckets = match env::var("METRICS_SIZE_BUCKETS") {
+        Ok(list) if !list.trim().is_empty() => {
+            let mut buckets = list
+                .split(',')
+                .map(str::trim)
+                .filter(|b| !b.is_empty())
+                .map(|b| match b.parse::<u64>() {
+                    Ok(n) if n > 0 => Ok(n),
+                    _ => anyhow::bail!("invalid METRICS_SIZE_BUCKETS entry {b:?}: expected a positive byte count"),
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            buckets.sort_unstable();
+            buckets.dedup();
+            buckets
+        }
+        _ => metrics::DEFAULT_SIZE_BUCKETS.to_vec(),
+    };
+    let uploads_enabled = env_flag("UPLOADS_ENABLED", true);
+    let sanitize = SanitizeOptions {
+        disallowed: env::var("SANITIZE_CHARS")
+            .map(|v| v.chars().collect())
+            .unwrap_or_else(|_| DEFAULT_SANITIZE_CHARS.chars().collect()),
+        fallback: env::var("SANITIZE_FALLBACK")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "file".to_string()),
+        percent_encode: match env::var("SANITIZE_MODE").as_deref() {
+            Ok("percent") => true,
+            Ok("replace") | Err(_) => false,
+            Ok(other) => anyhow::bail!("invalid SANITIZE_MODE {other:?}, expected replace or percent"),
+        },
+    };
+    // 回退名本身也不能包含非法字符
+    if sanitize.fallback.chars().any(|c| c.is_control() || c == '/' || c == '\\' || sanitize.disallowed.contains(&c)) {
+        anyhow::bail!("SANITIZE_FALLBACK {:?} contains disallowed characters", sanitize.fallback);
+    }
+    let admin_token = env::var("ADMIN_TOKEN").ok().filter(|t| !t.trim().is_empty());
+    let edit_token = env::var("EDIT_TOKEN").ok().map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+    // 令牌会原样写进 Cookie，只允许 Cookie 值中合法的字符
+    if edit_token.as_ref().is_some_and(|t| !t.bytes().all(|b| b.is_ascii_graphic() && !b";,\\\"".contains(&b))) {
+        anyhow::bail!("EDIT_TOKEN may only contain printable ASCII without spaces, ';', ',', '\\' or '\"'");
+    }
+    let note_size_hard_cap: usize = env::var("NOTE_SIZE_HARD_CAP")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(2 * 1024 * 1024);
+    // BASE_PATH：部署在反向代理的子路径下时使用，如 /notes；规范化为无结尾斜杠，根路径为空串
+    let base_path = env::var("BASE_PATH").unwrap_or_default().trim().trim_end_matches('/').to_string();
+    let base_path = if base_path.is_empty() || base_path.starts_with('/') { base_path } else { format!("/{base_path}") };
+    if !base_path.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '-' | '_' | '.')) {
+        anyhow::bail!("invalid BASE_PATH {base_path:?}, expected a path like /notes");
+    }
+    let public_url = env::var("PUBLIC_URL").ok().map(|s| s.trim().trim_end_matches('/').to_string()).filter(|s| !s.is_empty());
+    if let Some(url) = &public_url {
+        if !(url.starts_with("http://") || url.starts_with("https://")) || url.contains(['?', '#', ' ']) {
+            anyhow::bail!("invalid PUBLIC_URL {url:?}, expected an absolute URL like https://example.com/notes");
+        }
+    }
+    let trust_proxy_headers = env_flag("TRUST_PROXY_HEADERS", false);
+    let pwa_manifest = env_flag("ENABLE_PWA", false).then(|| {
+        let name = env::var("PWA_NAME").unwrap_or_else(|_| "web-mini-note".to_string());
+        let icon = env::var("PWA_ICON").unwrap_or_else(|_| format!("{base_path}/favicon.ico"));
+        let manifest = serde_json::json!({
+            "name": name,
+            "short_name": name,
+            "start_url": format!("{base_path}/"),
+            "display": "standalone",
+            "background_color": "#ffffff",
+            "theme_color": "#ffffff",
+            "icons": [{ "src": icon, "sizes": "any", "type": mime_guess::from_path(&icon).first_or_octet_stream().essence_str() }],
+        });
+        Arc::new(manifest.to_string())
+    });
+    let custom_css = env::var("CUSTOM_CSS_PATH").ok().filter(|s| !s.trim().is_empty()).map(PathBuf::from);
+    if let Some(path) = &custom_css {
+        if !path.is_file() {
+            anyhow::bail!("CUSTOM_CSS_PATH {path:?} does not exist or is not a file");
+        }
+        if let Err(e) = fs::File::open(path) {
+            anyhow::bail!("CUSTOM_CSS_PATH {path:?} is not readable: {e}");
+        }
+    }
+    // 公告内容启动时读取一次；默认按纯文本转义，SITE_NOTICE_HTML 开启时原样插入（运维自己提供的内容）
+    let notice_text = match (
+        env::var("SITE_NOTICE").ok().filter(|s| !s.trim().is_empty()),
+        env::var("SITE_NOTICE_PATH").ok().filter(|s| !s.trim().is_empty()),
+    ) {
+        (Some(_), Some(_)) => anyhow::bail!("SITE_NOTICE and SITE_NOTICE_PATH cannot both be set"),
+        (Some(text), None) => Some(text),
+        (None, Some(path)) => {
+            Some(fs::read_to_string(&path).with_context(|| format!("SITE_NOTICE_PATH {path:?} is not readable"))?)
+        }
+        (None, None) => None,
+    };
+    let site_notice_html = env_flag("SITE_NOTICE_HTML", false);
+    let site_notice = notice_text
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+        .map(|text| if site_notice_html { text } else { html_escape(&text) });
+    let templates_dir = env::var("TEMPLATES_DIR").ok().filter(|s| !s.trim().is_empty()).map(PathBuf::from);
+    if let Some(dir) = &templates_dir {
+        if !dir.is_dir() {
+            anyhow::bail!("TEMPLATES_DIR {dir:?} does not exist or is not a directory");
+        }
+    }
+    let mut pinned_notes: Vec<String> = Vec::new();
+    for id in env::var("PINNED_NOTES").unwrap_or_default().split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if !NOTE_RE.is_match(id) {
+            anyhow::bail!("invalid note id {id:?} in PINNED_NOTES");
+        }
+        if !pinned_notes.iter().any(|n| n == id) {
+            pinned_notes.push(id.to_string());
+        }
+    }
+    let read_limiter = env::var("READ_RATE_LIMIT")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|&n: &u32| n > 0)
+        .map(|n| Arc::new(ratelimit::RateLimiter::new(n, std::time::Duration::from_secs(60))));
+    let upload_name_length = env::var("UPLOAD_NAME_LENGTH").ok().and_then(|s| s.parse().ok()).unwrap_or(40);
+    let upload_slots = env::var("MAX_UPLOADS_PER_IP")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|&n: &usize| n > 0)
+        .map(|n| Arc::new(ratelimit::ConcurrencyLimiter::new(n)));
+    let file_slots = env::var("MAX_FILE_SERVES")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|&n: &usize| n > 0)
+        .map(|n| Arc::new(ratelimit::FileSlots::new(n)));
+    let raw_newline = match env::var("RAW_NEWLINE") {
+        Ok(v) => RawNewline::parse(&v)
+            .with_context(|| format!("invalid RAW_NEWLINE {v:?}, expected verbatim, nl or trim"))?,
+        Err(_) => RawNewline::Verbatim,
+    };
+    let compress_notes = env_flag("COMPRESS_NOTES", false);
+    let auto_render = env_flag("AUTO_RENDER_MARKDOWN", false);
+    let verify_content = env_flag("VERIFY_CONTENT_HASH", false);
+    let pretty_json_default = env_flag("PRETTY_JSON", false);
+    let debug_timing = env_flag("DEBUG_TIMING", false);
+    let legacy_cache_headers = env_flag("LEGACY_CACHE_HEADERS", true);
+    let insert_format = match env::var("UPLOAD_INSERT_FORMAT").unwrap_or_default().trim() {
+        "" | "markdown" => InsertFormat::Markdown,
+        "html" => InsertFormat::Html,
+        other => anyhow::bail!("invalid UPLOAD_INSERT_FORMAT {other:?}, expected markdown or html"),
+    };
+    let editor_display_limit = env::var("EDITOR_DISPLAY_LIMIT")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(1024 * 1024);
+    let max_decompressed_body = env::var("MAX_DECOMPRESSED_BODY")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|&n: &usize| n > 0)
+        .unwrap_or(MAX_DECOMPRESSED_BODY);
+    let time_format = match (env::var("TIME_FORMAT").ok(), env::var("TZ").is_ok()) {
+        (Some(f), _) => Some(f),
+        (None, true) => Some("%Y-%m-%d %H:%M:%S %:z".to_string()),
+        (None, false) => None,
+    };
+    if let Some(f) = &time_format {
+        if chrono::format::StrftimeItems::new(f).any(|item| item == chrono::format::Item::Error) {
+            anyhow::bail!("invalid TIME_FORMAT {f:?}");
+        }
+    }
+    let max_tmp_files = env::var("MAX_TMP_FILES").ok().and_then(|s| s.parse().ok()).unwrap_or(0);
+    let empty_post_clears = match env::var("EMPTY_POST_BEHAVIOR").as_deref() {
+        Ok("delete") | Err(_) => false,
+        Ok("clear") => true,
+        Ok(other) => anyhow::bail!("invalid EMPTY_POST_BEHAVIOR {other:?}, expected delete or clear"),
+    };
+    let embed_origins = env::var("EMBED_ORIGINS").unwrap_or_else(|_| "*".to_string());
+    let cors_origins = env::var("CORS_ORIGINS").unwrap_or_else(|_| "*".to_string());
+    // 预检结果缓存秒数；不设置时不发送 Access-Control-Max-Age
+    let cors_max_age: Option<u64> = env::var("CORS_MAX_AGE").ok().and_then(|s| s.parse().ok());
+    let cors_credentials = env_flag("CORS_ALLOW_CREDENTIALS", false);
+    // 规范不允许通配来源与 credentials 同时使用
+    if cors_credentials && matches!(cors_origins.trim(), "" | "*") {
+        anyhow::bail!("CORS_ALLOW_CREDENTIALS requires an explicit CORS_ORIGINS list, not \"*\"");
+    }
+    let js_cache_max_age: u64 = env::var("JS_CACHE_MAX_AGE").ok().and_then(|s| s.parse().ok()).unwrap_or(604800);
+    let upload_cache_max_age: u64 = env::var("UPLOAD_CACHE_MAX_AGE")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(2592000);
+    let track_access_time = env_flag("TRACK_ACCESS_TIME", false);
+    let note_max_idle_days: u64 = env::var("NOTE_MAX_IDLE_DAYS").ok().and_then(|s| s.parse().ok()).unwrap_or(0);
+    let listing_enabled = env_flag("LISTING_ENABLED", false);
+    let max_list_results = env::var("MAX_LIST_RESULTS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(1000);
+    let excerpt_length = env::var("EXCERPT_LENGTH").ok().and_then(|s| s.parse().ok()).unwrap_or(150);
+    let snippet_length = env::var("SNIPPET_LENGTH").ok().and_then(|s| s.parse().ok()).unwrap_or(80);
+
+    let access_log = match env::var("ACCESS_LOG_PATH") {
+        Ok(path) if !path.is_empty() => Some(
+            access_log::AccessLog::open(FsPath::new(&path))
+                .with_context(|| format!("ACCESS_LOG_PATH {path:?} is not writable"))?,
+        ),
+        _ => None,
+    };
+
+    ensure_writable_dir(FsPath::new(&save_path)).with_context(|| format!("SAVE_PATH {save_path:?} is not usable"))?;
+    if env_flag("SEED_WELCOME_NOTE", false) {
+        seed_welcome_note(FsPath::new(&save_path), compress_notes);
+    }
+    // 审计日志不能放在 SAVE_PATH 下，否则可能被当作 note 或上传文件读写
+    let audit_log = match env::var("AUDIT_LOG_PATH") {
+        Ok(path) if !path.is_empty() => {
+            let parent = FsPath::new(&path).parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(FsPath::new("."));
+            let inside = match (fs::canonicalize(parent), fs::canonicalize(&save_path)) {
+                (Ok(dir), Ok(save)) => dir.starts_with(save),
+                _ => false,
+            };
+            if inside {
+                anyhow::bail!("AUDIT_LOG_PATH {path:?} must not be inside SAVE_PATH");
+            }
+            Some(
+                audit::AuditLog::open(FsPath::new(&path))
+                    .with_context(|| format!("AUDIT_LOG_PATH {path:?} is not writable"))?,
+            )
+        }
+        _ => None,
+    };
+    // 备份目录与 SAVE_PATH 不能互相包含
+    let backup = match env::var("BACKUP_PATH") {
+        Ok(path) if !path.is_empty() => {
+            fs::create_dir_all(&path).with_context(|| format!("BACKUP_PATH {path:?} cannot be created"))?;
+            let nested = match (fs::canonicalize(&path), fs::canonicalize(&save_path)) {
+                (Ok(dir), Ok(save)) => dir.starts_with(&save) || save.starts_with(&dir),
+                _ => false,
+            };
+            if nested {
+                anyhow::bail!("BACKUP_PATH {path:?} must not overlap with SAVE_PATH");
+            }
+            Some(
+                backup::Backup::new(PathBuf::from(&path), Arc::new(PathBuf::from(&save_path)))
+                    .with_context(|| format!("BACKUP_PATH {path:?} is not writable"))?,
+            )
+        }
+        _ => None,
+    };
+    let backup_initial_sync = env_flag("BACKUP_INITIAL_SYNC", false);
+    if !FsPath::new(&static_root).is_dir() {
+        anyhow::bail!("STATIC_ROOT {static_root:?} does not exist or is not a directory");
+    }
+
+    // STATIC_ROOT 配错时所有资源都会 404，页面无样式且没有任何提示
+    let missing = missing_assets(FsPath::new(&static_root));
+    if !missing.is_empty() {
+        if env_flag("STATIC_ROOT_STRICT", false) {
+            anyhow::bail!("STATIC_ROOT {static_root:?} is missing assets: {}", missing.join(", "));
+        }
+        warn!(
+            "STATIC_ROOT {static_root:?} is missing assets: {} (the page will render broken)",
+            missing.join(", ")
+        );
+    }
 
-    fs::create_dir_all(&save_path)?;
+    // 启动时输出生效的配置，便于从日志确认部署是否符合预期；口令类配置只输出是否设置
+    info!(
+        save_path = %save_path,
+        static_root = %static_root,
+        static_extensions = %static_extensions.join(","),
+        file_limit,
+        single_file_size_limit,
+        size_limit_behavior = if truncate_oversize { "truncate" } else { "reject" },
+        note_size_hard_cap,
+        max_tmp_files,
+        max_decompressed_body,
+        editor_display_limit,
+        "storage config"
+    );
+    info!(
+        uploads_enabled,
+        listing_enabled,
+        track_access_time,
+        note_max_idle_days,
+        metrics_enabled,
+        pwa_enabled = pwa_manifest.is_some(),
+        custom_css = custom_css.is_some(),
+        site_notice = site_notice.is_some(),
+        templates = templates_dir.is_some(),
+        pinned_notes = pinned_notes.len(),
+        access_log = access_log.is_some(),
+        audit_log = audit_log.is_some(),
+        backup = backup.is_some(),
+        read_rate_limit = read_limiter.is_some(),
+        max_uploads_per_ip = upload_slots.as_ref().map_or(0, |l| l.max()),
+        max_file_serves = file_slots.as_ref().map_or(0, |l| l.max()),
+        strict_note_ids,
+        reject_short_ids,
+        strict_query,
+        empty_post_clears,
+        compress_notes,
+        auto_render,
+        verify_content,
+        pretty_json = pretty_json_default,
+        debug_timing,
+        legacy_cache_headers,
+        "feature config"
+    );
+    info!(
+        admin_token = if admin_token.is_some() { "set" } else { "unset" },
+        edit_token = if edit_token.is_some() { "set" } else { "unset" },
+        cors_origins = %cors_origins,
+        cors_max_age = cors_max_age.unwrap_or(0),
+        cors_credentials,
+        embed_origins = %embed_origins,
+        base_path = %base_path,
+        public_url = public_url.as_deref().unwrap_or("-"),
+        trust_proxy_headers,
+        "access config"
+    );
 
+    let access_times = track_access_time.then(|| Arc::new(access_times::AccessTimes::load(FsPath::new(&save_path))));
+    let chunked = chunked::ChunkedUploads::new(
+        FsPath::new(&save_path),
+        std::time::Duration::from_secs(CHUNKED_UPLOAD_TTL),
+        MAX_PENDING_UPLOADS,
+    );
+    let pwa_enabled = pwa_manifest.is_some();
+    let custom_css_enabled = custom_css.is_some();
     let state = AppState {
         save_path: Arc::new(PathBuf::from(save_path)),
         file_limit,
         single_file_size_limit,
+        truncate_oversize,
         static_root: Arc::new(PathBuf::from(static_root)),
+        static_extensions: Arc::new(static_extensions),
+        strict_note_ids,
+        reject_short_ids,
+        strict_query,
+        listing_enabled,
+        max_list_results,
+        excerpt_length,
+        snippet_length,
+        stats: Arc::new(FileStats::default()),
+        access_times,
+        root_behavior,
+        note_id_length,
+        random_alphabet: Arc::new(random_alphabet),
+        note_id_retries,
+        note_id_retry_warn,
+        metrics: Arc::new(Metrics::new(&size_buckets)),
+        uploads_enabled,
+        embed_origins: Arc::new(embed_origins),
+        sanitize: Arc::new(sanitize),
+        admin_token: admin_token.map(|t| Arc::new(t.trim().to_string())),
+        edit_token: edit_token.map(Arc::new),
+        note_size_hard_cap,
+        time_format: time_format.map(Arc::new),
+        max_decompressed_body,
+        compress_notes,
+        insert_format,
+        auto_render,
+        verify_content,
+        audit_log,
+        backup,
+        empty_post_clears,
+        chunked: Arc::new(chunked),
+        public_url: public_url.map(Arc::new),
+        trust_proxy_headers,
+        upload_keys: Arc::new(idempotency::IdempotencyCache::new(
+            std::time::Duration::from_secs(IDEMPOTENCY_TTL),
+            MAX_IDEMPOTENCY_KEYS,
+        )),
+        base_path: Arc::new(base_path.clone()),
+        pwa_manifest,
+        custom_css: custom_css.map(Arc::new),
+        site_notice: site_notice.map(Arc::new),
+        templates_dir: templates_dir.map(Arc::new),
+        editor_display_limit,
+        pinned_notes: Arc::new(pinned_notes),
+        note_max_idle_days,
+        read_limiter,
+        upload_slots,
+        file_slots,
+        upload_name_length,
+        legacy_cache_headers,
+        raw_newline,
+        max_tmp_files,
     };
 
-    let app = Router::new()
+    // 大目录扫描可能较慢，放到后台进行，不阻塞监听
+    {
+        let stats = state.stats.clone();
+        let dir = state.save_path.clone();
+        tokio::task::spawn_blocking(move || match stats.scan(&dir) {
+            Ok(()) => info!(
+                "file stats ready: {} files, {} bytes",
+                stats.file_count().unwrap_or(0),
+                stats.total_bytes().unwrap_or(0)
+            ),
+            Err(e) => error!("file stats scan error: {e}"),
+        });
+    }
+
+    // 读取时间每分钟写盘一次
+    if let Some(times) = state.access_times.clone() {
+        let dir = state.save_path.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(std::time::Duration::from_secs(60));
+            loop {
+                tick.tick().await;
+                let (times, dir) = (times.clone(), dir.clone());
+                if let Ok(Err(e)) = tokio::task::spawn_blocking(move || times.flush(&dir)).await {
+                    error!("access times flush error: {e}");
+                }
+            }
+        });
+    }
+
+    // 启动时把现有文件全量复制到备份目录
+    if let Some(backup) = state.backup.clone().filter(|_| backup_initial_sync) {
+        tokio::task::spawn_blocking(move || match backup.full_sync() {
+            Ok(n) => info!("backup initial sync copied {n} files"),
+            Err(e) => error!("backup initial sync error: {e}"),
+        });
+    }
+
+    // 闲置 note 每小时清理一次
+    if state.note_max_idle_days > 0 {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(std::time::Duration::from_secs(3600));
+            loop {
+                tick.tick().await;
+                let state = state.clone();
+                if let Ok(Err(e)) = tokio::task::spawn_blocking(move || reap_idle_notes(&state)).await {
+                    error!("idle note cleanup error: {e}");
+                }
+            }
+        });
+    }
+
+    // 路由按用途分组，各组使用独立的缓存/CORS 策略：
+    // - note 与 API：不缓存（处理函数自带 no-cache 头），CORS 按 CORS_ORIGINS（默认允许所有来源）
+    // - /js 第三方库与 /_tmp 上传文件：长缓存（JS_CACHE_MAX_AGE / UPLOAD_CACHE_MAX_AGE）；/_tmp 只返回上传文件名格式的文件
+    // - 其余静态资源（styles.css、script.js 等）没有版本号，仍不缓存；静态资源 CORS 全部放开
+    // - STATIC_ROOT 根目录下的文件（STATIC_EXTENSIONS）由 /:note 在 ID 不合法时兜底返回，不缓存
+    let notes = Router::new()
         .route("/", get(get_root))
-        .route("/:note", get(get_note).post(post_note))
-        .route("/upload", post(upload_file))
-        .route("/_tmp/:file", get(serve_tmp_file))
-        // 静态资源（映射到现有文件）
-        .route("/styles.css", get(serve_file))
-        .route("/clippy.svg", get(serve_file))
-        .route("/favicon.ico", get(serve_file))
-        .route("/script.js", get(serve_file))
-        .route("/copy.js", get(serve_file))
-        .route("/markdown.js", get(serve_file))
-        .route("/history.js", get(serve_file))
+        .route(
+            "/:note",
+            get(get_note).post(post_note).layer(DefaultBodyLimit::max(max_decompressed_body)),
+        )
+        .route("/:note/alias", post(create_alias))
+        .route("/:note/fork", post(fork_note))
+        .route("/:note/rename", post(rename_note))
+        .route("/:note/embed", get(embed_note))
+        .route("/:note/fragment", get(note_fragment))
+        .route("/:note/download", get(download_note))
+        .route("/:note/excerpt", get(note_excerpt))
+        .route("/:note/share", get(share_note))
+        .route("/:note/check", post(check_note))
+        .route("/:note/limit", post(set_size_limit))
+        .route("/:note/meta", get(note_meta))
+        .route("/:note/immutable", post(set_immutable))
+        .route("/:note/noindex", post(set_noindex))
+        .route("/:note/description", post(set_description))
+        .route(
+            "/upload",
+            post(upload_file).layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE + 64 * 1024)),
+        )
+        .route("/upload/init", post(upload_init))
+        .route(
+            "/upload/:id",
+            get(upload_status).patch(upload_chunk).layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route("/upload/:id/complete", post(upload_complete))
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), require_edit));
+    let api = Router::new()
+        .route("/api/notes", get(list_notes))
+        .route("/api/stats", get(get_stats))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/api/exists/:note", get(note_exists))
+        .route("/api/validate-id/:candidate", get(validate_note_id))
+        .route("/api/edit-token", post(set_edit_cookie))
+        .route("/api/pinned", get(list_pinned))
+        .route("/api/templates", get(list_templates))
+        .route("/api/config", get(get_config))
+        .route("/api/notes/delete", post(bulk_delete))
+        .route("/api/notes/get", post(batch_get));
+    let api = if metrics_enabled { api.route("/metrics", get(get_metrics)) } else { api };
+    let dynamic = notes.merge(api).layer(cors_layer(&cors_origins, cors_max_age, cors_credentials)?);
+
+    let assets = Router::new()
         .route("/js/:file", get(serve_public_js))
-        .with_state(state)
-        .layer(CorsLayer::permissive())
-        .layer(TraceLayer::new_for_http());
+        .layer(cache_layer(js_cache_max_age));
+    // 关闭上传时不暴露 /_tmp
+    let assets = if uploads_enabled {
+        let uploads = Router::new()
+            .route("/_tmp/:file", get(serve_tmp_file))
+            .route("/_tmp/:file/list", get(list_tmp_archive))
+            .layer(cache_layer(upload_cache_max_age));
+        assets.merge(uploads)
+    } else {
+        assets
+    };
+    let assets = if pwa_enabled {
+        assets.route("/manifest.json", get(get_manifest))
+    } else {
+        assets
+    };
+    let assets = if custom_css_enabled { assets.route("/custom.css", get(serve_custom_css)) } else { assets };
+    let assets = assets.layer(CorsLayer::permissive());
+
+    // /ping 不经过 CORS 层，也不访问存储，供外部监控做最廉价的探测
+    let app = dynamic.merge(assets).route("/ping", get(ping)).with_state(state);
+    // 子路径部署时 "/notes/" 与 "/notes" 等价（nest 只匹配后者）
+    let app = if base_path.is_empty() {
+        app
+    } else {
+        let root = base_path.clone();
+        Router::new()
+            .nest(&base_path, app)
+            .route(&format!("{base_path}/"), get(move || async move { Redirect::to(&root) }))
+    };
+    let app = app.layer(TraceLayer::new_for_http());
+    let app = app.layer(axum::middleware::from_fn(move |req, next| pretty_json(pretty_json_default, req, next)));
+    let app = if debug_timing { app.layer(axum::middleware::from_fn(response_time)) } else { app };
+    let app = match access_log {
+        Some(log) => app.layer(axum::middleware::from_fn(move |req, next| {
+            access_log::middleware(log.clone(), req, next)
+        })),
+        None => app,
+    };
+
+    let opts = server::ServerOptions {
+        backlog: env::var("LISTEN_BACKLOG").ok().and_then(|s| s.parse().ok()).unwrap_or(1024),
+        http_keepalive: env_flag("HTTP_KEEPALIVE", true),
+        tcp_keepalive: env::var("TCP_KEEPALIVE_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|&secs| secs > 0)
+            .map(std::time::Duration::from_secs),
+        header_read_timeout: Some(
+            env::var("HEADER_READ_TIMEOUT_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(30),
+        )
+        .filter(|&secs| secs > 0)
+        .map(std::time::Duration::from_secs),
+        max_header_size: env::var("MAX_HEADER_SIZE").ok().and_then(|s| s.parse().ok()).unwrap_or(16384),
+    };
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let listener = server::bind(addr, opts.backlog)?;
+    info!(
+        backlog = opts.backlog,
+        http_keepalive = opts.http_keepalive,
+        tcp_keepalive = ?opts.tcp_keepalive,
+        header_read_timeout = ?opts.header_read_timeout,
+        max_header_size = opts.max_header_size,
+        "listening on {addr}"
+    );
+    server::serve(listener, app, opts).await;
+    Ok(())
+}
+
+fn env_flag(name: &str, default: bool) -> bool {
+    match env::var(name) {
+        Ok(v) => matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"),
+        Err(_) => default,
+    }
+}
 
-    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
-    info!("listening on {}", port);
-    axum::serve(listener, app).await?;
+// 确认目录存在（不存在则创建）且可写，出错时给出具体原因
+fn ensure_writable_dir(dir: &FsPath) -> anyhow::Result<()> {
+    if dir.exists() && !dir.is_dir() {
+        anyhow::bail!("path exists but is not a directory");
+    }
+    fs::create_dir_all(dir).context("cannot create directory")?;
+    let probe = dir.join(".write-test");
+    fs::write(&probe, b"").context("directory is not writable")?;
+    let _ = fs::remove_file(&probe);
     Ok(())
 }
 
+// CORS_ORIGINS：* 表示允许所有来源，否则为逗号分隔的来源列表
+fn cors_layer(origins: &str, max_age: Option<u64>, credentials: bool) -> anyhow::Result<CorsLayer> {
+    let origins = origins.trim();
+    let layer = if origins.is_empty() || origins == "*" {
+        CorsLayer::permissive()
+    } else {
+        let list = origins
+            .split(',')
+            .map(str::trim)
+            .filter(|o| !o.is_empty())
+            .map(|o| {
+                if o == "*" {
+                    anyhow::bail!("CORS_ORIGINS cannot mix \"*\" with explicit origins");
+                }
+                HeaderValue::from_str(o).with_context(|| format!("invalid CORS origin {o:?}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let layer = CorsLayer::new().allow_origin(AllowOrigin::list(list));
+        // 带 credentials 时方法和请求头也不能用通配，改为回显预检请求中的值
+        if credentials {
+            layer
+                .allow_methods(AllowMethods::mirror_request())
+                .allow_headers(AllowHeaders::mirror_request())
+                .allow_credentials(true)
+        } else {
+            layer.allow_methods(Any).allow_headers(Any)
+        }
+    };
+    Ok(match max_age {
+        Some(secs) => layer.max_age(std::time::Duration::from_secs(secs)),
+        None => layer,
+    })
+}
+
+// max_age 为 0 时不缓存；处理函数自己设置了 Cache-Control 的响应（如 503）保持不变
+fn cache_layer(max_age: u64) -> SetResponseHeaderLayer<HeaderValue> {
+    let value = if max_age == 0 {
+        HeaderValue::from_static("no-cache, no-store, must-revalidate")
+    } else {
+        HeaderValue::from_str(&format!("public, max-age={max_age}")).unwrap()
+    };
+    SetResponseHeaderLayer::if_not_present(CACHE_CONTROL, value)
+}
+
+// 全新的 SAVE_PATH（还没有任何 note）中创建 welcome 说明页；已有 note 时不创建，
+// 避免用户删掉后每次重启又出现；create_new 保证不覆盖已有文件
+fn seed_welcome_note(save_path: &FsPath, compress: bool) {
+    let has_notes = fs::read_dir(save_path).map_or(true, |rd| {
+        rd.flatten().any(|e| !e.file_name().to_string_lossy().starts_with('.'))
+    });
+    if has_notes {
+        return;
+    }
+    let written = store::encode(WELCOME_NOTE.as_bytes(), compress).and_then(|data| {
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(save_path.join(WELCOME_NOTE_ID))
+            .and_then(|mut f| io::Write::write_all(&mut f, &data))
+    });
+    match written {
+        Ok(()) => info!("created welcome note {WELCOME_NOTE_ID}"),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => warn!("create welcome note error: {e}"),
+    }
+}
+
+fn missing_assets(root: &FsPath) -> Vec<&'static str> {
+    KEY_ASSETS.iter().copied().filter(|a| !root.join(a).is_file()).collect()
+}
+
 fn init_tracing() {
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
     tracing_subscriber::fmt().with_env_filter(filter).init();
 }
 
-async fn get_root() -> impl IntoResponse {
-    Redirect::to(&format!("/{}", random_note_id(5)))
+async fn get_root(State(state): State<AppState>) -> Response {
+    match &state.root_behavior {
+        RootBehavior::Random => Redirect::to(&format!("{}/{}", state.base_path, new_note_id(&state))).into_response(),
+        RootBehavior::Fixed(note) => Redirect::to(&format!("{}/{note}", state.base_path)).into_response(),
+        RootBehavior::Landing => {
+            let mut resp = Html(render_landing_html(&new_note_id(&state), &state.base_path)).into_response();
+            resp.headers_mut().extend(no_cache_headers(&state));
+            resp
+        }
+    }
 }
 
 #[derive(Deserialize, Default)]
 struct NoteQuery {
     raw: Option<String>,
+    view: Option<String>,
+    mime: Option<String>,
+    // raw 输出的字节切片：?offset=N&length=M，utf8=1 时收缩到完整的 UTF-8 字符
+    offset: Option<String>,
+    length: Option<String>,
+    utf8: Option<String>,
+    // 新建 note 时用 TEMPLATES_DIR 中的模板预填编辑框
+    template: Option<String>,
+    // 以下参数由前端（?marked 分享链接）或中间件（?pretty）处理，这里只为 STRICT_QUERY 登记
+    #[allow(dead_code)]
+    marked: Option<String>,
+    #[allow(dead_code)]
+    pretty: Option<String>,
+    // 未识别的参数，STRICT_QUERY 开启时据此返回 400
+    #[serde(flatten)]
+    extra: std::collections::BTreeMap<String, String>,
 }
 
 async fn get_note(
     State(state): State<AppState>,
     Path(note): Path<String>,
     Query(query): Query<NoteQuery>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
+    uri: Uri,
 ) -> Response {
-    // 校验 note
+    // 校验 note；不是合法 ID 时先看是否为 STATIC_ROOT 下的静态文件
     if !NOTE_RE.is_match(&note) {
-        return Redirect::to(&format!("/{}", random_note_id(5))).into_response();
+        if let Some(resp) = serve_root_asset(&state, &note, &headers) {
+            return resp;
+        }
+        return invalid_note_response(&state, &headers);
+    }
+
+    if state.strict_query && !query.extra.is_empty() {
+        let keys: Vec<_> = query.extra.keys().map(String::as_str).collect();
+        return (StatusCode::BAD_REQUEST, format!("unknown query parameters: {}", keys.join(", "))).into_response();
+    }
+
+    if let Err(retry_after) = check_read_limit(&state, remote, 1) {
+        return too_many_requests(retry_after);
     }
 
     let note_path = state.save_path.join(&note);
+    if let Some(resp) = directory_conflict(&note_path, &note) {
+        return resp;
+    }
+
+    // 别名：跳转到真实 note，保留查询参数
+    if !note_path.is_file() {
+        if let Some(target) = meta::load(&state.save_path, &note).alias_of {
+            let location = match uri.query() {
+                Some(q) => format!("{}/{target}?{q}", state.base_path),
+                None => format!("{}/{target}", state.base_path),
+            };
+            return Redirect::to(&location).into_response();
+        }
+    }
 
-    // no-cache 头
-    let base_headers = no_cache_headers();
+    if let Some(times) = &state.access_times {
+        if note_path.is_file() {
+            times.touch(&note);
+        }
+    }
+    let note_meta = meta::load(&state.save_path, &note);
+    // no-cache 头；标记为 noindex 的 note 对所有形式的输出都附带 X-Robots-Tag
+    let mut base_headers = no_cache_headers(&state);
+    if note_meta.noindex {
+        base_headers.insert("x-robots-tag", HeaderValue::from_static("noindex"));
+    }
 
     // raw 输出或 curl/wget UA
-    let ua = headers
-        .get("user-agent")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("");
-    let is_cli = ua.starts_with("curl") || ua.starts_with("Wget");
-    let want_raw = query.raw.is_some() || is_cli;
+    let is_cli = is_cli_client(&headers);
+    // 显式指定 view 或 ?raw=0 时不再按 UA 自动切换到 raw
+    let want_raw = match query.raw.as_deref() {
+        Some("0") | Some("false") => false,
+        Some(_) => true,
+        None => (is_cli && query.view.is_none()) || query.offset.is_some() || query.length.is_some(),
+    };
+    let newline = query.raw.as_deref().and_then(RawNewline::parse).unwrap_or(state.raw_newline);
 
     if want_raw {
-        if note_path.is_file() {
-            let Ok(bytes) = fs::read(&note_path) else {
-                return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
-            };
-            let mut resp = Response::builder()
-                .status(StatusCode::OK)
-                .header("content-type", "text/plain; charset=utf-8")
-                .body(bytes.into())
-                .unwrap();
-            resp.headers_mut().extend(base_headers.clone());
-            return resp;
-        } else {
-            let mut resp = Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .body(axum::body::Body::empty())
-                .unwrap();
-            resp.headers_mut().extend(base_headers.clone());
+        let content_type = raw_content_type(query.mime.as_deref(), note_meta.lang.as_deref());
+        if query.offset.is_some() || query.length.is_some() {
+            let mut resp = raw_slice_response(&note_path, &query, content_type);
+            resp.headers_mut().extend(base_headers);
             return resp;
         }
+        // 不事先判断文件是否存在：检查与读取之间可能被并发删除，直接按打开结果处理
+        let mut resp = match raw_note_body(&note_path, newline).await {
+            Ok((body, etag)) => {
+                let mut builder = Response::builder().status(StatusCode::OK).header("content-type", content_type);
+                if let Some(verified) = verify_content(&state, &note, &note_meta, || etag.clone()) {
+                    builder = builder.header("x-content-verified", verified);
+                }
+                if let Some(etag) = etag {
+                    builder = builder.header("etag", etag);
+                }
+                builder.body(body).unwrap()
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap(),
+            Err(e) => {
+                error!("read error: {e}");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+            }
+        };
+        resp.headers_mut().extend(base_headers);
+        return resp;
     }
 
-    // HTML 页面
-    let content_escaped = if note_path.is_file() {
-        match fs::read_to_string(&note_path) {
-            Ok(s) => html_escape(&s),
-            Err(_) => String::new(),
+    // 只读页面：服务端渲染 markdown，不带编辑器
+    if query.view.as_deref() == Some("read") {
+        let (status, text) = match store::read_note_string(&note_path) {
+            Ok(s) => (StatusCode::OK, s),
+            Err(_) => (StatusCode::NOT_FOUND, String::new()),
+        };
+        let excerpt = note_meta.description.clone().unwrap_or_else(|| generate_excerpt(&text, state.excerpt_length));
+        let html = render_read_html(&note, &markdown::render_note(&text, note_meta.lang.as_deref()), &excerpt, &state.base_path);
+        let mut resp = (status, Html(html)).into_response();
+        resp.headers_mut().extend(base_headers);
+        if status == StatusCode::OK {
+            set_verified_header(&mut resp, verify_content(&state, &note, &note_meta, || Some(content_etag(text.as_bytes()))));
         }
+        return resp;
+    }
+
+    // HTML 页面；note 不存在时可用模板预填，用户编辑后才会保存
+    let content = if note_path.is_file() {
+        store::read_note_string(&note_path).unwrap_or_default()
     } else {
-        String::new()
+        query.template.as_deref().and_then(|name| load_template(&state, &note, name)).unwrap_or_default()
+    };
+    let excerpt = note_meta.description.clone().unwrap_or_else(|| generate_excerpt(&content, state.excerpt_length));
+    // 超大 note 只内联开头部分，完整内容由页面按需通过 ?raw 加载
+    let truncated = state.editor_display_limit > 0 && content.len() > state.editor_display_limit;
+    let content_escaped = if truncated {
+        html_escape(&content[..floor_char_boundary(&content, state.editor_display_limit)])
+    } else {
+        html_escape(&content)
     };
 
-    let excerpt = generate_excerpt_by_path(&note_path);
-    let html = render_html(&note, &content_escaped, &excerpt);
+    // 冻结、被截断或没有编辑权限时不提供上传入口
+    let immutable = note_meta.immutable;
+    let locked = !can_edit(&state, &headers);
+    let pinned = existing_pinned(&state);
+    let opts = PageOptions {
+        base: &state.base_path,
+        pinned: &pinned,
+        uploads_enabled: state.uploads_enabled && !immutable && !truncated && !locked,
+        pwa: state.pwa_manifest.is_some(),
+        custom_css: state.custom_css.is_some(),
+        notice: state.site_notice.as_deref().map(String::as_str),
+        insert_format: state.insert_format,
+        immutable,
+        truncated,
+        locked,
+        noindex: note_meta.noindex,
+        // 截断显示的内容不完整，不自动渲染
+        render: state.auto_render
+            && !truncated
+            && note_meta.lang.is_none()
+            && markdown::looks_like_markdown(&content),
+    };
+    let html = render_html(&note, &content_escaped, &excerpt, &opts);
     let mut resp = Html(html).into_response();
     resp.headers_mut().extend(base_headers);
+    if note_path.is_file() {
+        set_verified_header(&mut resp, verify_content(&state, &note, &note_meta, || Some(content_etag(content.as_bytes()))));
+    }
     resp
 }
 
-#[derive(Deserialize)]
-struct PostForm {
-    text: Option<String>,
+// VERIFY_CONTENT_HASH：与写入时记录的哈希比较，不一致说明文件被损坏或在服务之外被改动。
+// 未开启、没有记录（如开启前写入的 note）或内容过大未计算哈希时返回 None，不输出校验头
+fn verify_content(
+    state: &AppState,
+    note: &str,
+    note_meta: &meta::NoteMeta,
+    etag: impl FnOnce() -> Option<String>,
+) -> Option<&'static str> {
+    if !state.verify_content {
+        return None;
+    }
+    let expected = note_meta.content_hash.as_deref()?;
+    if etag()? == expected {
+        return Some("true");
+    }
+    warn!("note {note} content does not match its stored hash, the file may be corrupted");
+    Some("false")
 }
 
-async fn post_note(
-    State(state): State<AppState>,
-    Path(note): Path<String>,
-    Form(form): Form<PostForm>,
-) -> Response {
-    if !NOTE_RE.is_match(&note) {
-        return Redirect::to(&format!("/{}", random_note_id(5))).into_response();
+fn set_verified_header(resp: &mut Response, verified: Option<&'static str>) {
+    if let Some(verified) = verified {
+        resp.headers_mut().insert("x-content-verified", HeaderValue::from_static(verified));
     }
+}
 
-    let text = form.text.unwrap_or_default();
-
-    // 文件数量限制
-    match count_files_in_dir(&state.save_path) {
-        Ok(count) if count >= state.file_limit => {
-            error!("File limit reached {}", state.file_limit);
-            return StatusCode::FORBIDDEN.into_response();
-        }
-        Ok(_) => {}
+// ?offset=&length= 的 raw 切片：返回 206 与 Content-Range；offset 超出内容长度时返回 416。
+// offset 等于内容长度时返回空内容，便于脚本按上次的长度轮询追加的部分
+fn raw_slice_response(note_path: &FsPath, query: &NoteQuery, content_type: &str) -> Response {
+    let parse = |v: Option<&str>| v.map(|s| s.trim().parse::<usize>()).transpose();
+    let (Ok(offset), Ok(length)) = (parse(query.offset.as_deref()), parse(query.length.as_deref())) else {
+        return (StatusCode::BAD_REQUEST, "offset and length must be non-negative integers").into_response();
+    };
+    let content = match store::read_note(note_path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return StatusCode::NOT_FOUND.into_response(),
         Err(e) => {
-            error!("count files error: {e}");
+            error!("read error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
+    };
+    let total = content.len();
+    let mut start = offset.unwrap_or(0);
+    if start > total {
+        return (StatusCode::RANGE_NOT_SATISFIABLE, [("content-range", format!("bytes */{total}"))]).into_response();
     }
-
-    // 单文件大小限制（按字节计算）
-    if text.as_bytes().len() > state.single_file_size_limit {
-        error!("File size limit reached {}", state.single_file_size_limit);
-        return StatusCode::FORBIDDEN.into_response();
-    }
-
-    let note_path = state.save_path.join(&note);
-    if text.is_empty() {
-        // 删除文件（如果存在）
-        if note_path.exists() {
-            let _ = fs::remove_file(&note_path);
+    let mut end = length.map_or(total, |len| start.saturating_add(len).min(total));
+    if matches!(query.utf8.as_deref(), Some("1") | Some("true")) {
+        // UTF-8 续字节为 10xxxxxx：起点向后、终点向前移到字符边界
+        while start < end && content[start] & 0xC0 == 0x80 {
+            start += 1;
         }
-    } else {
-        if let Err(e) = fs::write(&note_path, text) {
-            error!("write error: {e}");
-            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        while end > start && end < total && content[end] & 0xC0 == 0x80 {
+            end -= 1;
         }
     }
-    StatusCode::OK.into_response()
+    let range = if start < end { format!("bytes {start}-{}/{total}", end - 1) } else { format!("bytes */{total}") };
+    (
+        StatusCode::PARTIAL_CONTENT,
+        [("content-type", content_type.to_string()), ("content-range", range)],
+        content[start..end].to_vec(),
+    )
+        .into_response()
 }
 
-async fn serve_file(State(state): State<AppState>, uri: Uri) -> impl IntoResponse {
-    // 从 static_root 读取同名文件
-    let rel = uri.path().trim_start_matches('/');
-    let path = state.static_root.join(rel);
-    match fs::read(&path) {
-        Ok(bytes) => {
-            let mime = mime_guess::from_path(&path).first_or_octet_stream();
-            let mut headers = no_cache_headers();
-            headers.insert("content-type", HeaderValue::from_str(mime.as_ref()).unwrap());
-            let mut resp = Response::builder().status(StatusCode::OK).body(bytes.into()).unwrap();
-            resp.headers_mut().extend(headers);
-            resp
-        }
-        Err(_) => StatusCode::NOT_FOUND.into_response(),
+// 小文件直接读入内存并附带 ETag（按解压后的内容计算）；超过阈值的明文大文件按块流式输出，
+// 避免大 note 撑爆内存，此时不计算 ETag，也不做末尾换行处理
+async fn raw_note_body(path: &FsPath, newline: RawNewline) -> io::Result<(Body, Option<String>)> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let meta = file.metadata().await?;
+    if !meta.is_file() {
+        return Err(io::ErrorKind::NotFound.into());
     }
+    let len = meta.len();
+    let mut magic = [0u8; 2];
+    let compressed = len >= 2 && file.read_exact(&mut magic).await.is_ok() && store::is_compressed(&magic);
+    file.seek(io::SeekFrom::Start(0)).await?;
+    if len <= RAW_STREAM_THRESHOLD || compressed {
+        let mut buf = Vec::with_capacity(len as usize);
+        file.read_to_end(&mut buf).await?;
+        let mut buf = store::decode(buf)?;
+        let etag = content_etag(&buf);
+        newline.apply(&mut buf);
+        return Ok((buf.into(), Some(etag)));
+    }
+    Ok((Body::from_stream(ReaderStream::new(file)), None))
 }
 
-async fn serve_public_js(State(state): State<AppState>, Path(file): Path<String>) -> impl IntoResponse {
-    let safe = file.replace("../", "");
-    let path = state.static_root.join("public").join("js").join(safe);
-    match fs::read(&path) {
-        Ok(bytes) => {
-            let mime = mime_guess::from_path(&path).first_or_octet_stream();
-            let mut headers = no_cache_headers();
-            headers.insert("content-type", HeaderValue::from_str(mime.as_ref()).unwrap());
-            let mut resp = Response::builder().status(StatusCode::OK).body(bytes.into()).unwrap();
-            resp.headers_mut().extend(headers);
-            resp
-        }
-        Err(_) => StatusCode::NOT_FOUND.into_response(),
+#[derive(Deserialize)]
+struct PostForm {
+    text: Option<String>,
+    // 可选的语言提示；不传则保持原值，传空值恢复默认的 markdown
+    lang: Option<String>,
+}
+
+// 只接受表单编码的请求体：JSON 或纯文本按表单解析会得到空的 text，进而把 note 清空或删除
+fn parse_post_form(headers: &HeaderMap, body: &[u8], max: usize) -> Result<PostForm, (StatusCode, &'static str)> {
+    let is_form = header_str(headers, "content-type")
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|t| t.trim().eq_ignore_ascii_case("application/x-www-form-urlencoded"));
+    if !is_form {
+        return Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, "expected application/x-www-form-urlencoded"));
     }
+    let body = decode_body(headers, body, max)?;
+    serde_urlencoded::from_bytes::<PostForm>(&body).map_err(|_| (StatusCode::BAD_REQUEST, "invalid form body"))
 }
 
-async fn serve_tmp_file(State(state): State<AppState>, Path(file): Path<String>) -> impl IntoResponse {
-    let safe = file.replace("../", "");
-    let path = state.save_path.join(safe);
-    match fs::read(&path) {
-        Ok(bytes) => {
-            let mime = mime_guess::from_path(&path).first_or_octet_stream();
-            let mut headers = no_cache_headers();
-            headers.insert("content-type", HeaderValue::from_str(mime.as_ref()).unwrap());
-            let mut resp = Response::builder().status(StatusCode::OK).body(bytes.into()).unwrap();
-            resp.headers_mut().extend(headers);
-            resp
-        }
-        Err(_) => StatusCode::NOT_FOUND.into_response(),
+// 不超过 max 字节的最大 UTF-8 字符边界
+fn floor_char_boundary(text: &str, max: usize) -> usize {
+    if max >= text.len() {
+        return text.len();
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
     }
+    end
 }
 
-async fn upload_file(State(state): State<AppState>, mut multipart: Multipart) -> impl IntoResponse {
-    // 限制 100MB
-    const MAX_SIZE: usize = 100 * 1024 * 1024;
+// 写入前的校验结果；post_note 与 /:note/check 共用
+enum WriteRejection {
+    Immutable,
+    ShortId { min: usize },
+    FileLimit { count: usize },
+    TooLarge { limit: usize },
+}
 
-    // 保存到 _tmp 下，文件名加时间戳避免冲突
-    while let Ok(Some(field)) = multipart.next_field().await {
-        if let Some(name) = field.name().map(|s| s.to_string()) {
-            if name != "file" { continue; }
+fn check_write(state: &AppState, note: &str, size: usize) -> Result<(), WriteRejection> {
+    if meta::load(&state.save_path, note).immutable {
+        return Err(WriteRejection::Immutable);
+    }
+    if state.reject_short_ids && note.len() < state.note_id_length && !state.save_path.join(note).is_file() {
+        return Err(WriteRejection::ShortId { min: state.note_id_length });
+    }
+
+    // 文件数量限制
+    match current_file_count(state) {
+        Ok(count) if count >= state.file_limit => return Err(WriteRejection::FileLimit { count }),
+        Ok(_) => {}
+        Err(e) => {
+            error!("count files error: {e}");
+        }
+    }
+
+    // 单文件大小限制（按字节计算），单独设置过上限的 note 以其为准
+    let limit = note_size_limit(state, note);
+    if size > limit {
+        return Err(WriteRejection::TooLarge { limit });
+    }
+    Ok(())
+}
+
+// 只做校验不写入，客户端在粘贴大段内容前先确认能否保存
+async fn check_note(
+    State(state): State<AppState>,
+    Path(note): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if !NOTE_RE.is_match(&note) {
+        return (StatusCode::BAD_REQUEST, INVALID_NOTE_ID).into_response();
+    }
+    let form = match parse_post_form(&headers, &body, state.max_decompressed_body) {
+        Ok(form) => form,
+        Err(e) => return e.into_response(),
+    };
+    let size = form.text.as_deref().map_or(0, str::len);
+    if let Some(Err(msg)) = form.lang.as_deref().map(meta::normalize_lang) {
+        return json_response(
+            StatusCode::OK,
+            &serde_json::json!({ "ok": false, "reason": "invalid_lang", "message": msg, "size": size }),
+        );
+    }
+
+    let note = resolve_alias(&state, note);
+    let value = match check_write(&state, &note, size) {
+        Ok(()) => serde_json::json!({ "ok": true, "size": size, "limit": note_size_limit(&state, &note) }),
+        Err(WriteRejection::Immutable) => serde_json::json!({ "ok": false, "reason": "immutable", "size": size }),
+        Err(WriteRejection::ShortId { min }) => {
+            serde_json::json!({ "ok": false, "reason": "note_id_too_short", "size": size, "min_length": min })
+        }
+        Err(WriteRejection::FileLimit { count }) => serde_json::json!({
+            "ok": false, "reason": "file_limit_reached", "size": size, "limit": state.file_limit, "count": count,
+        }),
+        Err(WriteRejection::TooLarge { limit }) if state.truncate_oversize => {
+            serde_json::json!({ "ok": true, "truncated": true, "size": size, "limit": limit })
+        }
+        Err(WriteRejection::TooLarge { limit }) => {
+            serde_json::json!({ "ok": false, "reason": "too_large", "size": size, "limit": limit })
+        }
+    };
+    json_response(StatusCode::OK, &value)
+}
+
+async fn post_note(
+    State(state): State<AppState>,
+    Path(note): Path<String>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if !NOTE_RE.is_match(&note) {
+        // 命令行客户端跟随重定向后会把内容写进一个随机 note，直接报错更清楚
+        if is_cli_client(&headers) {
+            return (StatusCode::BAD_REQUEST, INVALID_NOTE_ID).into_response();
+        }
+        return invalid_note_response(&state, &headers);
+    }
+
+    let form = match parse_post_form(&headers, &body, state.max_decompressed_body) {
+        Ok(form) => form,
+        Err(e) => return e.into_response(),
+    };
+    let mut text = form.text.unwrap_or_default();
+    let lang = match form.lang.as_deref().map(meta::normalize_lang).transpose() {
+        Ok(lang) => lang,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+
+    // 写入别名时落到真实 note 上
+    let note = resolve_alias(&state, note);
+
+    let mut truncated = false;
+    match check_write(&state, &note, text.len()) {
+        Ok(()) => {}
+        Err(WriteRejection::Immutable) => {
+            return (StatusCode::CONFLICT, "note is immutable").into_response();
+        }
+        Err(WriteRejection::ShortId { min }) => return short_id_response(min),
+        Err(WriteRejection::FileLimit { count }) => return file_limit_response(&state, count),
+        Err(WriteRejection::TooLarge { limit }) if state.truncate_oversize => {
+            warn!("note {note} truncated from {} to {limit} bytes", text.len());
+            text.truncate(floor_char_boundary(&text, limit));
+            truncated = true;
+        }
+        Err(WriteRejection::TooLarge { limit }) => {
+            error!("File size limit reached {}", limit);
+            return StatusCode::FORBIDDEN.into_response();
+        }
+    }
+
+    let note_path = state.save_path.join(&note);
+    if let Some(resp) = directory_conflict(&note_path, &note) {
+        return resp;
+    }
+    let old_size = fs::metadata(&note_path).ok().filter(|m| m.is_file()).map(|m| m.len());
+
+    // 条件写入：If-None-Match: * 仅在 note 不存在时创建；If-Match 要求当前内容的 ETag 匹配
+    let create_only = header_str(&headers, "if-none-match").is_some_and(|v| v.trim() == "*");
+    if create_only && old_size.is_some() {
+        return StatusCode::PRECONDITION_FAILED.into_response();
+    }
+    if let Some(expected) = header_str(&headers, "if-match") {
+        let current = store::read_note(&note_path).ok().map(|c| content_etag(&c));
+        if !etag_matches(expected, current.as_deref()) {
+            return StatusCode::PRECONDITION_FAILED.into_response();
+        }
+    }
+
+    if text.is_empty() && !state.empty_post_clears {
+        // 删除文件（如果存在）
+        if let Ok(Some(old)) = delete_note(&state, &note) {
+            audit(&state, "delete", &note, remote, old);
+        }
+    } else {
+        let written = if create_only {
+            // create_new 保证并发创建时只有一个请求成功
+            store::encode(text.as_bytes(), state.compress_notes).and_then(|data| {
+                fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&note_path)
+                    .and_then(|mut f| io::Write::write_all(&mut f, &data))
+                    .map(|_| data.len() as u64)
+            })
+        } else {
+            store::write_note(&note_path, text.as_bytes(), state.compress_notes)
+        };
+        let disk_size = match written {
+            Ok(n) => n,
+            Err(e) => {
+                if e.kind() == io::ErrorKind::AlreadyExists {
+                    return StatusCode::PRECONDITION_FAILED.into_response();
+                }
+                error!("write error: {e}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        state.stats.record_write(old_size, disk_size);
+        state.metrics.note_write_bytes.observe(text.len() as u64);
+        audit(&state, if old_size.is_some() { "update" } else { "create" }, &note, remote, disk_size);
+        let etag = content_etag(text.as_bytes());
+        // 关闭校验时清掉旧的哈希，避免之后重新开启时误报
+        let hash = state.verify_content.then(|| etag.clone());
+        let mut note_meta = meta::load(&state.save_path, &note);
+        if lang.as_ref().is_some_and(|lang| note_meta.lang != *lang) || note_meta.content_hash != hash {
+            if let Some(lang) = lang {
+                note_meta.lang = lang;
+            }
+            note_meta.content_hash = hash;
+            if let Err(e) = meta::save(&state.save_path, &note, &note_meta) {
+                error!("save meta error: {e}");
+            }
+        }
+        backup(&state, &note);
+        let mut resp = StatusCode::OK.into_response();
+        if let Ok(v) = HeaderValue::from_str(&etag) {
+            resp.headers_mut().insert("etag", v);
+        }
+        if truncated {
+            resp.headers_mut().insert("x-note-truncated", HeaderValue::from_static("true"));
+        }
+        return resp;
+    }
+    StatusCode::OK.into_response()
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    limit: Option<usize>,
+    q: Option<String>,
+    // modified（默认）或 accessed（需开启 TRACK_ACCESS_TIME）
+    sort: Option<String>,
+}
+
+// 列出/搜索 note；无论 ?limit= 多大，返回条数都不超过 MAX_LIST_RESULTS
+async fn list_notes(
+    State(state): State<AppState>,
+    Query(query): Query<ListQuery>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+) -> Response {
+    if !state.listing_enabled {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    // 列表带有各 note 的摘要（搜索时还会读取全部内容），计为一次读取
+    if let Err(retry_after) = check_read_limit(&state, remote, 1) {
+        return too_many_requests(retry_after);
+    }
+
+    let cap = state.max_list_results;
+    let limit = query.limit.unwrap_or(cap).min(cap);
+    let needle = query.q.as_deref().map(str::to_lowercase).filter(|q| !q.is_empty());
+    let by_access = match query.sort.as_deref() {
+        None | Some("modified") => false,
+        Some("accessed") if state.access_times.is_some() => true,
+        Some("accessed") => {
+            return (StatusCode::BAD_REQUEST, "sort=accessed requires TRACK_ACCESS_TIME").into_response();
+        }
+        Some(_) => return (StatusCode::BAD_REQUEST, "sort must be modified or accessed").into_response(),
+    };
+
+    let entries = match fs::read_dir(state.save_path.as_path()) {
+        Ok(rd) => rd,
+        Err(e) => {
+            error!("list notes error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let mut notes = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !NOTE_RE.is_match(&name) {
+            continue;
+        }
+        let Ok(meta) = entry.metadata() else { continue };
+        if !meta.is_file() {
+            continue;
+        }
+        if let Some(needle) = &needle {
+            let in_name = name.to_lowercase().contains(needle.as_str());
+            let in_content = || {
+                store::read_note_string(&entry.path())
+                    .map(|c| c.to_lowercase().contains(needle.as_str()))
+                    .unwrap_or(false)
+            };
+            if !in_name && !in_content() {
+                continue;
+            }
+        }
+        let modified = modified_secs(&meta);
+        // 没有读取记录的 note 按修改时间计
+        let accessed = state.access_times.as_ref().map(|t| t.get(&name).unwrap_or(modified).max(modified));
+        // size 为内容大小，与 /:note/meta 一致（COMPRESS_NOTES 开启时不是磁盘上的文件大小）
+        let size = store::content_len(&entry.path()).unwrap_or(meta.len());
+        notes.push((name, size, modified, accessed));
+    }
+
+    // 最近修改（或最近读取）的排在前面
+    let key = |n: &(String, u64, u64, Option<u64>)| if by_access { n.3.unwrap_or(n.2) } else { n.2 };
+    notes.sort_by(|a, b| key(b).cmp(&key(a)).then_with(|| a.0.cmp(&b.0)));
+    let total = notes.len();
+    let truncated = total > limit;
+    notes.truncate(limit);
+
+    // SNIPPET_LENGTH=0 时不读取内容、不返回摘要
+    let items: Vec<_> = notes
+        .into_iter()
+        .map(|(note, size, modified, accessed)| {
+            let mut item = serde_json::json!({ "note": note, "size": size });
+            set_modified(&state, &mut item, modified);
+            if let Some(accessed) = accessed.and_then(|secs| chrono::DateTime::from_timestamp(secs as i64, 0)) {
+                item["accessed"] = accessed.to_rfc3339_opts(chrono::SecondsFormat::Secs, true).into();
+            }
+            if state.snippet_length > 0 {
+                item["snippet"] = generate_excerpt_by_path(&state.save_path.join(&note), state.snippet_length).into();
+            }
+            item
+        })
+        .collect();
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({
+            "notes": items,
+            "total": total,
+            "truncated": truncated,
+            "max_results": cap,
+        }),
+    )
+}
+
+// 只返回摘要（EXCERPT_LENGTH），供链接预览等场景使用；Accept 为 JSON 时返回 {note, excerpt}
+async fn note_excerpt(
+    State(state): State<AppState>,
+    Path(note): Path<String>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    if !NOTE_RE.is_match(&note) {
+        return (StatusCode::BAD_REQUEST, "invalid note id").into_response();
+    }
+    if let Err(retry_after) = check_read_limit(&state, remote, 1) {
+        return too_many_requests(retry_after);
+    }
+    let note = resolve_alias(&state, note);
+    let path = state.save_path.join(&note);
+    if !path.is_file() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let excerpt = generate_excerpt_by_path(&path, state.excerpt_length);
+    let mut resp = if header_str(&headers, "accept").is_some_and(|a| a.contains("application/json")) {
+        json_response(StatusCode::OK, &serde_json::json!({ "note": note, "excerpt": excerpt }))
+    } else {
+        excerpt.into_response()
+    };
+    resp.headers_mut().extend(no_cache_headers(&state));
+    resp
+}
+
+// 以附件形式下载 note，文件名按语言提示取扩展名（默认 markdown 为 .md）
+async fn download_note(
+    State(state): State<AppState>,
+    Path(note): Path<String>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+) -> Response {
+    if !NOTE_RE.is_match(&note) {
+        return (StatusCode::BAD_REQUEST, "invalid note id").into_response();
+    }
+    if let Err(retry_after) = check_read_limit(&state, remote, 1) {
+        return too_many_requests(retry_after);
+    }
+    let note = resolve_alias(&state, note);
+    let content = match store::read_note(&state.save_path.join(&note)) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("read error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let lang = meta::load(&state.save_path, &note).lang;
+    let ext = match lang.as_deref() {
+        None => "md",
+        Some("json") => "json",
+        Some("csv") => "csv",
+        Some(_) => "txt",
+    };
+    // note ID 只含 [a-zA-Z0-9_-]，可以直接放进文件名
+    let disposition = format!("attachment; filename=\"{note}.{ext}\"");
+    let mut resp = (
+        [
+            ("content-type", meta::lang_content_type(lang.as_deref())),
+            ("content-disposition", disposition.as_str()),
+        ],
+        content,
+    )
+        .into_response();
+    resp.headers_mut().extend(no_cache_headers(&state));
+    resp
+}
+
+// note 的附加信息（语言提示、大小、修改时间等），不返回内容
+async fn note_meta(State(state): State<AppState>, Path(note): Path<String>) -> Response {
+    if !NOTE_RE.is_match(&note) {
+        return (StatusCode::BAD_REQUEST, "invalid note id").into_response();
+    }
+    let note = resolve_alias(&state, note);
+    let file = match fs::metadata(state.save_path.join(&note)) {
+        Ok(m) if m.is_file() => m,
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let note_meta = meta::load(&state.save_path, &note);
+    // size 为内容大小；COMPRESS_NOTES 开启时与磁盘上的文件大小不同
+    let content = store::read_note(&state.save_path.join(&note)).ok();
+    let mut info = serde_json::json!({
+        "note": note,
+        "lang": note_meta.lang.as_deref().unwrap_or("markdown"),
+        "size": content.as_ref().map_or(file.len(), |c| c.len() as u64),
+        "size_limit": note_size_limit(&state, &note),
+        "etag": content.as_deref().map(content_etag),
+        "immutable": note_meta.immutable,
+        "noindex": note_meta.noindex,
+        "description": note_meta.description,
+    });
+    set_modified(&state, &mut info, modified_secs(&file));
+    json_response(StatusCode::OK, &info)
+}
+
+// 按服务端规则校验自定义 note ID，客户端无需自己维护一份可能过时的正则
+async fn validate_note_id(State(state): State<AppState>, Path(candidate): Path<String>) -> Response {
+    let reason = if !NOTE_RE.is_match(&candidate) {
+        Some("invalid_pattern")
+    } else if RESERVED_NOTE_IDS.contains(&candidate.as_str()) {
+        Some("reserved")
+    } else if state.reject_short_ids
+        && candidate.len() < state.note_id_length
+        && !state.save_path.join(&candidate).is_file()
+    {
+        Some("too_short")
+    } else {
+        None
+    };
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({ "id": candidate, "valid": reason.is_none(), "reason": reason, "pattern": NOTE_RE.as_str() }),
+    )
+}
+
+// 只报告 note 是否存在及大小，不返回内容
+async fn note_exists(State(state): State<AppState>, Path(note): Path<String>) -> Response {
+    if !NOTE_RE.is_match(&note) {
+        return (StatusCode::BAD_REQUEST, "invalid note id").into_response();
+    }
+    let note = resolve_alias(&state, note);
+    let path = state.save_path.join(&note);
+    match fs::metadata(&path) {
+        Ok(meta) if meta.is_file() => json_response(
+            StatusCode::OK,
+            &serde_json::json!({ "note": note, "exists": true, "size": store::content_len(&path).unwrap_or(meta.len()) }),
+        ),
+        _ => json_response(
+            StatusCode::NOT_FOUND,
+            &serde_json::json!({ "note": note, "exists": false, "size": 0 }),
+        ),
+    }
+}
+
+// 置顶 note 中当前存在的部分（别名按其指向的 note 判断），保持配置顺序
+fn existing_pinned(state: &AppState) -> Vec<String> {
+    state
+        .pinned_notes
+        .iter()
+        .filter(|note| state.save_path.join(resolve_alias(state, note.to_string())).is_file())
+        .cloned()
+        .collect()
+}
+
+async fn list_pinned(State(state): State<AppState>) -> Response {
+    let pinned = existing_pinned(&state);
+    let mut resp = json_response(StatusCode::OK, &serde_json::json!({ "pinned": pinned }));
+    resp.headers_mut().extend(no_cache_headers(&state));
+    resp
+}
+
+// 模板目录中可用的模板名：文件名去掉 .md/.txt 扩展名后需符合 note ID 规则
+fn template_names(dir: &FsPath) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        let ext = path.extension().and_then(|e| e.to_str());
+        if !matches!(ext, None | Some("md") | Some("txt")) {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()).map(str::to_string) else { continue };
+        if NOTE_RE.is_match(&name) && !names.iter().any(|(n, _)| *n == name) {
+            names.push((name, path));
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+// 按名称读取模板；名称非法、模板不存在或超过该 note 的大小上限时返回 None，编辑框保持为空
+fn load_template(state: &AppState, note: &str, name: &str) -> Option<String> {
+    let dir = state.templates_dir.as_deref()?;
+    if !NOTE_RE.is_match(name) {
+        return None;
+    }
+    let found = template_names(dir)
+        .map_err(|e| error!("read TEMPLATES_DIR error: {e}"))
+        .ok()?
+        .into_iter()
+        .find(|(n, _)| n == name);
+    let Some((_, path)) = found else {
+        warn!("template {name:?} not found");
+        return None;
+    };
+    let text = fs::read_to_string(&path).map_err(|e| error!("read template {name:?} error: {e}")).ok()?;
+    if text.len() > note_size_limit(state, note) {
+        warn!("template {name:?} exceeds the note size limit");
+        return None;
+    }
+    Some(text)
+}
+
+async fn list_templates(State(state): State<AppState>) -> Response {
+    let Some(dir) = state.templates_dir.as_deref() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match template_names(dir) {
+        Ok(names) => {
+            let names: Vec<_> = names.into_iter().map(|(name, _)| name).collect();
+            let mut resp = json_response(StatusCode::OK, &serde_json::json!({ "templates": names }));
+            resp.headers_mut().extend(no_cache_headers(&state));
+            resp
+        }
+        Err(e) => {
+            error!("read TEMPLATES_DIR error: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn ping() -> &'static str {
+    "pong"
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+// 就绪检查：在 .meta 下用保留文件做一次写-读-删，确认存储真正可用
+async fn readyz(State(state): State<AppState>) -> Response {
+    let dir = state.save_path.join(meta::META_DIR);
+    let probe = dir.join(".health");
+    let payload = chrono_like_timestamp();
+
+    let mut checks = serde_json::Map::new();
+    let write = fs::create_dir_all(&dir).and_then(|_| fs::write(&probe, &payload));
+    let read = match &write {
+        Ok(()) => fs::read_to_string(&probe).and_then(|s| {
+            if s == payload {
+                Ok(())
+            } else {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "read back mismatch"))
+            }
+        }),
+        Err(_) => Err(io::Error::other("skipped")),
+    };
+    let delete = match &write {
+        Ok(()) => fs::remove_file(&probe),
+        Err(_) => Err(io::Error::other("skipped")),
+    };
+
+    let mut healthy = true;
+    for (name, result) in [("write", write), ("read", read), ("delete", delete)] {
+        let value = match result {
+            Ok(()) => "ok".to_string(),
+            Err(e) => {
+                healthy = false;
+                e.to_string()
+            }
+        };
+        checks.insert(name.to_string(), value.into());
+    }
+    if !healthy {
+        error!("readiness check failed: {checks:?}");
+    }
+    let status = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    json_response(
+        status,
+        &serde_json::json!({ "status": if healthy { "ok" } else { "fail" }, "checks": checks }),
+    )
+}
+
+async fn get_metrics(State(state): State<AppState>) -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/plain; version=0.0.4")
+        .body(state.metrics.render().into())
+        .unwrap()
+}
+
+// 统计信息；启动扫描完成前 files/bytes 为 null
+async fn get_stats(State(state): State<AppState>) -> Response {
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({
+            "ready": state.stats.is_ready(),
+            "files": state.stats.file_count(),
+            "bytes": state.stats.total_bytes(),
+            "file_limit": state.file_limit,
+        }),
+    )
+}
+
+// 可嵌入 iframe 的精简页面：只有渲染后的 markdown，不加载任何脚本
+async fn embed_note(
+    State(state): State<AppState>,
+    Path(note): Path<String>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+) -> Response {
+    if !NOTE_RE.is_match(&note) {
+        return (StatusCode::BAD_REQUEST, "invalid note id").into_response();
+    }
+    if let Err(retry_after) = check_read_limit(&state, remote, 1) {
+        return too_many_requests(retry_after);
+    }
+    let note = resolve_alias(&state, note);
+    let Ok(text) = store::read_note_string(&state.save_path.join(&note)) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let note_meta = meta::load(&state.save_path, &note);
+
+    let html = format!(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">{robots}
+    <title>web-mini-note · {note}</title>
+    <link rel="stylesheet" href="{base}/styles.css">
+</head>
+<body>
+    <div id="markdown-content">{rendered}</div>
+</body>
+</html>
+"##,
+        note = note,
+        rendered = markdown::render_note(&text, note_meta.lang.as_deref()),
+        base = state.base_path,
+        robots = if note_meta.noindex { "\n    <meta name=\"robots\" content=\"noindex\">" } else { "" },
+    );
+
+    // 允许被 EMBED_ORIGINS 中的站点嵌入（空格或逗号分隔，默认 *）
+    let ancestors = state.embed_origins.replace(',', " ");
+    let csp = format!(
+        "default-src 'self'; img-src * data:; style-src 'self' 'unsafe-inline'; script-src 'none'; frame-ancestors {}",
+        ancestors.trim()
+    );
+    let mut resp = Html(html).into_response();
+    resp.headers_mut().extend(no_cache_headers(&state));
+    if note_meta.noindex {
+        resp.headers_mut().insert("x-robots-tag", HeaderValue::from_static("noindex"));
+    }
+    match HeaderValue::from_str(&csp) {
+        Ok(v) => {
+            resp.headers_mut().insert("content-security-policy", v);
+        }
+        Err(_) => error!("invalid EMBED_ORIGINS {:?}", state.embed_origins),
+    }
+    resp
+}
+
+// 只返回渲染后的 HTML 片段（无 <html>/<head>、无编辑器），供前端自行插入页面；
+// 与只读页面一样经过 render_note 的过滤，原始 HTML 不会被输出
+async fn note_fragment(
+    State(state): State<AppState>,
+    Path(note): Path<String>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+) -> Response {
+    if !NOTE_RE.is_match(&note) {
+        return (StatusCode::BAD_REQUEST, "invalid note id").into_response();
+    }
+    if let Err(retry_after) = check_read_limit(&state, remote, 1) {
+        return too_many_requests(retry_after);
+    }
+    let note = resolve_alias(&state, note);
+    let Ok(text) = store::read_note_string(&state.save_path.join(&note)) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let note_meta = meta::load(&state.save_path, &note);
+    let mut resp = Html(markdown::render_note(&text, note_meta.lang.as_deref())).into_response();
+    resp.headers_mut().extend(no_cache_headers(&state));
+    if note_meta.noindex {
+        resp.headers_mut().insert("x-robots-tag", HeaderValue::from_static("noindex"));
+    }
+    resp
+}
+
+// 管理员批量删除：请求体为 note ID 的 JSON 数组，逐个返回结果
+async fn bulk_delete(
+    State(state): State<AppState>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(notes): Json<Vec<String>>,
+) -> Response {
+    if let Err(status) = check_admin(&state, &headers) {
+        return status.into_response();
+    }
+    if notes.len() > MAX_BULK_DELETE {
+        return (StatusCode::PAYLOAD_TOO_LARGE, format!("at most {MAX_BULK_DELETE} notes per request")).into_response();
+    }
+
+    let mut deleted = 0;
+    let results: Vec<_> = notes
+        .into_iter()
+        .map(|note| {
+            let status = if !NOTE_RE.is_match(&note) {
+                "invalid".to_string()
+            } else if meta::load(&state.save_path, &note).immutable {
+                "immutable".to_string()
+            } else {
+                match delete_note(&state, &note) {
+                    Ok(Some(old)) => {
+                        deleted += 1;
+                        audit(&state, "delete", &note, remote, old);
+                        "deleted".to_string()
+                    }
+                    Ok(None) => "not_found".to_string(),
+                    Err(e) => {
+                        error!("bulk delete {note} error: {e}");
+                        format!("error: {e}")
+                    }
+                }
+            };
+            serde_json::json!({ "note": note, "status": status })
+        })
+        .collect();
+    info!("bulk delete removed {deleted} notes");
+    json_response(StatusCode::OK, &serde_json::json!({ "deleted": deleted, "results": results }))
+}
+
+// 批量读取 note，减少同步客户端的请求次数；缺失或非法的 ID 以 error 字段标出
+async fn batch_get(
+    State(state): State<AppState>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Json(notes): Json<Vec<String>>,
+) -> Response {
+    if notes.len() > MAX_BATCH_GET {
+        return (StatusCode::PAYLOAD_TOO_LARGE, format!("at most {MAX_BATCH_GET} notes per request")).into_response();
+    }
+    // 每个 note 都计入 READ_RATE_LIMIT，批量接口不能绕过读取限流
+    if let Err(retry_after) = check_read_limit(&state, remote, notes.len()) {
+        return too_many_requests(retry_after);
+    }
+
+    let results: Vec<_> = notes
+        .into_iter()
+        .map(|note| {
+            if !NOTE_RE.is_match(&note) {
+                return serde_json::json!({ "note": note, "error": "invalid" });
+            }
+            let path = state.save_path.join(resolve_alias(&state, note.clone()));
+            let file = fs::metadata(&path).ok().filter(|m| m.is_file());
+            match file.and_then(|m| store::read_note(&path).ok().map(|c| (m, c))) {
+                Some((m, content)) => {
+                    let mut item = serde_json::json!({
+                        "note": note,
+                        "content": String::from_utf8_lossy(&content),
+                        "etag": content_etag(&content),
+                    });
+                    set_modified(&state, &mut item, modified_secs(&m));
+                    item
+                }
+                None => serde_json::json!({ "note": note, "error": "not_found" }),
+            }
+        })
+        .collect();
+    json_response(StatusCode::OK, &serde_json::json!({ "notes": results }))
+}
+
+// READ_RATE_LIMIT：本次请求计入 count 次读取，超出时返回 Retry-After 秒数
+fn check_read_limit(state: &AppState, remote: SocketAddr, count: usize) -> Result<(), u64> {
+    match &state.read_limiter {
+        Some(limiter) => (0..count).try_for_each(|_| limiter.check(remote.ip())),
+        None => Ok(()),
+    }
+}
+
+fn too_many_requests(retry_after: u64) -> Response {
+    let mut resp = (StatusCode::TOO_MANY_REQUESTS, "too many requests").into_response();
+    resp.headers_mut().insert("retry-after", HeaderValue::from(retry_after));
+    resp
+}
+
+fn note_size_limit(state: &AppState, note: &str) -> usize {
+    meta::load(&state.save_path, note)
+        .size_limit
+        .map(|limit| limit.min(state.note_size_hard_cap))
+        .unwrap_or(state.single_file_size_limit)
+}
+
+#[derive(Deserialize)]
+struct SizeLimitForm {
+    limit: Option<String>,
+}
+
+// 管理员为单个 note 设置大小上限；limit 为空或 0 时恢复全局限制
+async fn set_size_limit(
+    State(state): State<AppState>,
+    Path(note): Path<String>,
+    headers: HeaderMap,
+    Form(form): Form<SizeLimitForm>,
+) -> Response {
+    if let Err(status) = check_admin(&state, &headers) {
+        return status.into_response();
+    }
+    if !NOTE_RE.is_match(&note) {
+        return (StatusCode::BAD_REQUEST, "invalid note id").into_response();
+    }
+    let note = resolve_alias(&state, note);
+    let limit = match form.limit.as_deref().map(str::trim) {
+        None | Some("") | Some("0") => None,
+        Some(v) => match v.parse::<usize>() {
+            Ok(n) if n <= state.note_size_hard_cap => Some(n),
+            Ok(_) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("limit exceeds NOTE_SIZE_HARD_CAP ({})", state.note_size_hard_cap),
+                )
+                    .into_response()
+            }
+            Err(_) => return (StatusCode::BAD_REQUEST, "invalid limit").into_response(),
+        },
+    };
+
+    let mut note_meta = meta::load(&state.save_path, &note);
+    note_meta.size_limit = limit;
+    if let Err(e) = meta::save(&state.save_path, &note, &note_meta) {
+        error!("size limit write error: {e}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    info!("size limit for {note} set to {limit:?}");
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({ "note": note, "size_limit": note_size_limit(&state, &note) }),
+    )
+}
+
+#[derive(Deserialize)]
+struct ImmutableForm {
+    immutable: Option<String>,
+}
+
+// 【管理】冻结/解冻 note：immutable=1 冻结，0 或留空解冻
+async fn set_immutable(
+    State(state): State<AppState>,
+    Path(note): Path<String>,
+    headers: HeaderMap,
+    Form(form): Form<ImmutableForm>,
+) -> Response {
+    if let Err(status) = check_admin(&state, &headers) {
+        return status.into_response();
+    }
+    if !NOTE_RE.is_match(&note) {
+        return (StatusCode::BAD_REQUEST, "invalid note id").into_response();
+    }
+    let note = resolve_alias(&state, note);
+    let immutable = matches!(
+        form.immutable.as_deref().map(str::trim),
+        Some("1") | Some("true") | Some("yes") | Some("on")
+    );
+
+    let mut note_meta = meta::load(&state.save_path, &note);
+    note_meta.immutable = immutable;
+    if let Err(e) = meta::save(&state.save_path, &note, &note_meta) {
+        error!("immutable flag write error: {e}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    info!("note {note} immutable set to {immutable}");
+    json_response(StatusCode::OK, &serde_json::json!({ "note": note, "immutable": immutable }))
+}
+
+#[derive(Deserialize)]
+struct NoindexForm {
+    noindex: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DescriptionForm {
+    description: Option<String>,
+}
+
+// 设置分享预览用的描述（meta description / og:description），留空恢复为自动摘要；
+// 换行与连续空白合并为一个空格
+async fn set_description(
+    State(state): State<AppState>,
+    Path(note): Path<String>,
+    Form(form): Form<DescriptionForm>,
+) -> Response {
+    if !NOTE_RE.is_match(&note) {
+        return (StatusCode::BAD_REQUEST, "invalid note id").into_response();
+    }
+    let note = resolve_alias(&state, note);
+    if !state.save_path.join(&note).is_file() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let description = form.description.unwrap_or_default().split_whitespace().collect::<Vec<_>>().join(" ");
+    if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("description is longer than {MAX_DESCRIPTION_LENGTH} characters"),
+        )
+            .into_response();
+    }
+
+    let mut note_meta = meta::load(&state.save_path, &note);
+    if note_meta.immutable {
+        return (StatusCode::CONFLICT, "note is immutable").into_response();
+    }
+    note_meta.description = Some(description).filter(|d| !d.is_empty());
+    if let Err(e) = meta::save(&state.save_path, &note, &note_meta) {
+        error!("description write error: {e}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({ "note": note, "description": note_meta.description }),
+    )
+}
+
+// 【管理】设置 note 是否禁止搜索引擎收录：noindex=1 禁止，0 或留空恢复
+async fn set_noindex(
+    State(state): State<AppState>,
+    Path(note): Path<String>,
+    headers: HeaderMap,
+    Form(form): Form<NoindexForm>,
+) -> Response {
+    if let Err(status) = check_admin(&state, &headers) {
+        return status.into_response();
+    }
+    if !NOTE_RE.is_match(&note) {
+        return (StatusCode::BAD_REQUEST, "invalid note id").into_response();
+    }
+    let note = resolve_alias(&state, note);
+    let noindex = matches!(
+        form.noindex.as_deref().map(str::trim),
+        Some("1") | Some("true") | Some("yes") | Some("on")
+    );
+
+    let mut note_meta = meta::load(&state.save_path, &note);
+    note_meta.noindex = noindex;
+    if let Err(e) = meta::save(&state.save_path, &note, &note_meta) {
+        error!("noindex flag write error: {e}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    info!("note {note} noindex set to {noindex}");
+    json_response(StatusCode::OK, &serde_json::json!({ "note": note, "noindex": noindex }))
+}
+
+// 运行时生效配置（管理接口），口令类配置只返回是否设置
+async fn get_config(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(status) = check_admin(&state, &headers) {
+        return status.into_response();
+    }
+    let mut resp = json_response(StatusCode::OK, &config_json(&state));
+    resp.headers_mut().extend(no_cache_headers(&state));
+    resp
+}
+
+fn config_json(state: &AppState) -> serde_json::Value {
+    let root_behavior = match &state.root_behavior {
+        RootBehavior::Random => "random".to_string(),
+        RootBehavior::Landing => "landing".to_string(),
+        RootBehavior::Fixed(note) => format!("fixed:{note}"),
+    };
+    let raw_newline = match state.raw_newline {
+        RawNewline::Verbatim => "verbatim",
+        RawNewline::Ensure => "nl",
+        RawNewline::Trim => "trim",
+    };
+    // 字段较多，分两段构造以免超出 json! 宏的递归上限
+    let mut config = serde_json::json!({
+        "save_path": state.save_path.as_path(),
+        "static_root": state.static_root.as_path(),
+        "base_path": state.base_path.as_str(),
+        "public_url": state.public_url.as_deref(),
+        "trust_proxy_headers": state.trust_proxy_headers,
+        "file_limit": state.file_limit,
+        "single_file_size_limit": state.single_file_size_limit,
+        "size_limit_behavior": if state.truncate_oversize { "truncate" } else { "reject" },
+        "note_size_hard_cap": state.note_size_hard_cap,
+        "max_decompressed_body": state.max_decompressed_body,
+        "max_tmp_files": state.max_tmp_files,
+        "compress_notes": state.compress_notes,
+        "empty_post_clears": state.empty_post_clears,
+        "audit_log": state.audit_log.is_some(),
+        "backup": state.backup.is_some(),
+        "strict_note_ids": state.strict_note_ids,
+        "reject_short_note_ids": state.reject_short_ids,
+        "strict_query": state.strict_query,
+        "root_behavior": root_behavior,
+        "raw_newline": raw_newline,
+        "note_id_length": state.note_id_length,
+        "random_alphabet": String::from_utf8_lossy(&state.random_alphabet),
+        "note_id_retries": state.note_id_retries,
+        "note_id_retry_warn": state.note_id_retry_warn,
+    });
+    let features = serde_json::json!({
+        "listing_enabled": state.listing_enabled,
+        "track_access_time": state.access_times.is_some(),
+        "max_list_results": state.max_list_results,
+        "excerpt_length": state.excerpt_length,
+        "snippet_length": state.snippet_length,
+        "uploads_enabled": state.uploads_enabled,
+        "upload_insert_format": state.insert_format.as_str(),
+        "upload_name_length": state.upload_name_length,
+        "legacy_cache_headers": state.legacy_cache_headers,
+        "auto_render_markdown": state.auto_render,
+        "verify_content_hash": state.verify_content,
+        "sanitize_chars": state.sanitize.disallowed.iter().collect::<String>(),
+        "sanitize_fallback": state.sanitize.fallback,
+        "sanitize_percent_encode": state.sanitize.percent_encode,
+        "embed_origins": state.embed_origins.as_str(),
+        "pwa_enabled": state.pwa_manifest.is_some(),
+        "pinned_notes": state.pinned_notes.as_slice(),
+        "note_max_idle_days": state.note_max_idle_days,
+        "custom_css": state.custom_css.as_deref(),
+        "site_notice": state.site_notice.is_some(),
+        "templates_dir": state.templates_dir.as_deref(),
+        "read_rate_limit": state.read_limiter.as_ref().map(|l| l.limit()),
+        "max_uploads_per_ip": state.upload_slots.as_ref().map(|l| l.max()),
+        "max_file_serves": state.file_slots.as_ref().map(|l| l.max()),
+        "time_format": state.time_format.as_deref(),
+        "admin_token": if state.admin_token.is_some() { "set" } else { "unset" },
+        "edit_token": if state.edit_token.is_some() { "set" } else { "unset" },
+        "stats_ready": state.stats.is_ready(),
+    });
+    if let (Some(config), serde_json::Value::Object(features)) = (config.as_object_mut(), features) {
+        config.extend(features);
+    }
+    config
+}
+
+// 管理接口鉴权：Authorization: Bearer <ADMIN_TOKEN> 或 X-Admin-Token；
+// 未配置 ADMIN_TOKEN 时管理接口整体关闭
+fn check_admin(state: &AppState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let Some(expected) = state.admin_token.as_deref() else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let provided = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-admin-token").and_then(|v| v.to_str().ok()))
+        .unwrap_or("");
+    if constant_time_eq(provided.trim().as_bytes(), expected.as_bytes()) {
+        Ok(())
+    } else {
+        Err(StatusCode::FORBIDDEN)
+    }
+}
+
+// 未设置 EDIT_TOKEN 时所有人都可编辑；否则需要 X-Edit-Token 或 edit_token Cookie，管理员令牌同样有效
+fn can_edit(state: &AppState, headers: &HeaderMap) -> bool {
+    let Some(expected) = state.edit_token.as_deref() else {
+        return true;
+    };
+    let from_cookie = headers
+        .get_all("cookie")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .any(|(name, value)| name == EDIT_COOKIE && constant_time_eq(value.as_bytes(), expected.as_bytes()));
+    from_cookie
+        || header_str(headers, "x-edit-token").is_some_and(|t| constant_time_eq(t.trim().as_bytes(), expected.as_bytes()))
+        || check_admin(state, headers).is_ok()
+}
+
+// 挂在 note/上传路由上：读请求放行，写请求需要编辑权限
+async fn require_edit(
+    State(state): State<AppState>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let read = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if read || can_edit(&state, req.headers()) {
+        return next.run(req).await;
+    }
+    json_response(
+        StatusCode::FORBIDDEN,
+        &serde_json::json!({ "error": "edit_token_required", "message": "this instance is read-only without the edit token" }),
+    )
+}
+
+#[derive(Deserialize)]
+struct EditTokenForm {
+    token: Option<String>,
+}
+
+// 校验编辑令牌并写入 Cookie；token 为空时清除 Cookie（退出编辑）
+async fn set_edit_cookie(State(state): State<AppState>, Form(form): Form<EditTokenForm>) -> Response {
+    let Some(expected) = state.edit_token.as_deref() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let token = form.token.as_deref().map(str::trim).unwrap_or("");
+    let path = if state.base_path.is_empty() { "/" } else { state.base_path.as_str() };
+    let cookie = if token.is_empty() {
+        format!("{EDIT_COOKIE}=; Path={path}; Max-Age=0; HttpOnly; SameSite=Lax")
+    } else if constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+        format!("{EDIT_COOKIE}={token}; Path={path}; Max-Age=31536000; HttpOnly; SameSite=Lax")
+    } else {
+        return (StatusCode::FORBIDDEN, "invalid edit token").into_response();
+    };
+    match HeaderValue::from_str(&cookie) {
+        Ok(v) => (StatusCode::NO_CONTENT, [("set-cookie", v)]).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Deserialize)]
+struct AliasForm {
+    alias: String,
+}
+
+// 为已有 note 创建别名，如 /standup -> /x7k2m
+async fn create_alias(
+    State(state): State<AppState>,
+    Path(note): Path<String>,
+    Form(form): Form<AliasForm>,
+) -> Response {
+    let alias = form.alias.trim().to_string();
+    if !NOTE_RE.is_match(&note) || !NOTE_RE.is_match(&alias) {
+        return (StatusCode::BAD_REQUEST, "invalid note id or alias").into_response();
+    }
+    // 与固定路由同名的别名会被路由遮住，无法访问
+    if RESERVED_NOTE_IDS.contains(&alias.as_str()) {
+        return (StatusCode::BAD_REQUEST, "alias is a reserved id").into_response();
+    }
+    // 别名同样是新 ID，受 REJECT_SHORT_NOTE_IDS 约束
+    if state.reject_short_ids && alias.len() < state.note_id_length {
+        return short_id_response(state.note_id_length);
+    }
+
+    // 目标总是解析到真实 note，别名不会指向别名，因此不会成环
+    let target = resolve_alias(&state, note);
+    if !state.save_path.join(&target).is_file() {
+        return (StatusCode::NOT_FOUND, "note not found").into_response();
+    }
+    if alias == target {
+        return (StatusCode::CONFLICT, "alias points to itself").into_response();
+    }
+    if state.save_path.join(&alias).exists() {
+        return (StatusCode::CONFLICT, "alias collides with an existing note").into_response();
+    }
+
+    let mut alias_meta = meta::load(&state.save_path, &alias);
+    match alias_meta.alias_of.as_deref() {
+        Some(existing) if existing != target => {
+            return (StatusCode::CONFLICT, "alias already points to another note").into_response();
+        }
+        _ => {}
+    }
+    alias_meta.alias_of = Some(target.clone());
+    if let Err(e) = meta::save(&state.save_path, &alias, &alias_meta) {
+        error!("alias write error: {e}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({ "alias": alias, "note": target, "url": format!("{}/{alias}", state.base_path) }),
+    )
+}
+
+// 复制 note 到一个新的随机 ID，用于把已有 note 当模板使用
+async fn fork_note(State(state): State<AppState>, Path(note): Path<String>) -> Response {
+    if !NOTE_RE.is_match(&note) {
+        return (StatusCode::BAD_REQUEST, "invalid note id").into_response();
+    }
+    let source = resolve_alias(&state, note);
+    let source_path = state.save_path.join(&source);
+    if !source_path.is_file() {
+        return (StatusCode::NOT_FOUND, "note not found").into_response();
+    }
+
+    match current_file_count(&state) {
+        Ok(count) if count >= state.file_limit => return file_limit_response(&state, count),
+        Ok(_) => {}
+        Err(e) => error!("count files error: {e}"),
+    }
+
+    let Some(fork) = unused_note_id(&state) else {
+        error!("fork: no free note id");
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    let size = match fs::copy(&source_path, state.save_path.join(&fork)) {
+        Ok(size) => size,
+        Err(e) => {
+            error!("fork copy error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    state.stats.record_write(None, size);
+
+    // 只复制描述内容本身的附加信息（语言、描述、内容哈希）；冻结、noindex 与管理员单独设置的
+    // 大小上限属于原 note，副本恢复默认，否则冻结的模板复制出来仍不可编辑，上限也可借复制扩散
+    let source_meta = meta::load(&state.save_path, &source);
+    let fork_meta = meta::NoteMeta {
+        lang: source_meta.lang,
+        description: source_meta.description,
+        content_hash: source_meta.content_hash,
+        ..meta::NoteMeta::default()
+    };
+    if let Err(e) = meta::save(&state.save_path, &fork, &fork_meta) {
+        error!("fork meta write error: {e}");
+    }
+    backup(&state, &fork);
+
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({ "note": fork, "from": source, "url": format!("{}/{fork}", state.base_path) }),
+    )
+}
+
+#[derive(Deserialize)]
+struct RenameForm {
+    to: String,
+}
+
+// 把 note 改名为指定 ID，附加信息随之移动，原有别名改为指向新 ID
+async fn rename_note(
+    State(state): State<AppState>,
+    Path(note): Path<String>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Form(form): Form<RenameForm>,
+) -> Response {
+    let to = form.to.trim().to_string();
+    if !NOTE_RE.is_match(&note) || !NOTE_RE.is_match(&to) {
+        return (StatusCode::BAD_REQUEST, "invalid note id").into_response();
+    }
+    if RESERVED_NOTE_IDS.contains(&to.as_str()) {
+        return (StatusCode::BAD_REQUEST, "target is a reserved id").into_response();
+    }
+    if state.reject_short_ids && to.len() < state.note_id_length {
+        return short_id_response(state.note_id_length);
+    }
+    let source = resolve_alias(&state, note);
+    let source_path = state.save_path.join(&source);
+    if !source_path.is_file() {
+        return (StatusCode::NOT_FOUND, "note not found").into_response();
+    }
+    if to == source {
+        return (StatusCode::CONFLICT, "target is the same note").into_response();
+    }
+    let source_meta = meta::load(&state.save_path, &source);
+    if source_meta.immutable {
+        return (StatusCode::CONFLICT, "note is immutable").into_response();
+    }
+    if meta::load(&state.save_path, &to).alias_of.is_some() {
+        return (StatusCode::CONFLICT, "target is an existing alias").into_response();
+    }
+
+    // 先硬链接再删除原文件：目标已存在时 hard_link 直接失败，不会覆盖别的 note
+    let target_path = state.save_path.join(&to);
+    if let Err(e) = fs::hard_link(&source_path, &target_path) {
+        if e.kind() == io::ErrorKind::AlreadyExists {
+            return (StatusCode::CONFLICT, "target note already exists").into_response();
+        }
+        error!("rename link error: {e}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    if let Err(e) = fs::remove_file(&source_path) {
+        error!("rename remove error: {e}");
+        let _ = fs::remove_file(&target_path);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    if let Err(e) = meta::save(&state.save_path, &to, &source_meta)
+        .and_then(|_| meta::save(&state.save_path, &source, &meta::NoteMeta::default()))
+    {
+        error!("rename meta error: {e}");
+    }
+    for alias in meta::aliases_of(&state.save_path, &source) {
+        let mut alias_meta = meta::load(&state.save_path, &alias);
+        alias_meta.alias_of = Some(to.clone());
+        if let Err(e) = meta::save(&state.save_path, &alias, &alias_meta) {
+            error!("rename alias {alias} error: {e}");
+        }
+    }
+    if let Some(times) = &state.access_times {
+        times.rename(&source, &to);
+    }
+    backup(&state, &source);
+    backup(&state, &to);
+    if let Some(log) = &state.audit_log {
+        log.record_rename(&source, &to, remote.ip(), fs::metadata(&target_path).map_or(0, |m| m.len()));
+    }
+    info!("renamed note {source} to {to}");
+
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({ "note": to, "from": source, "url": format!("{}/{to}", state.base_path) }),
+    )
+}
+
+// note 路径被目录占用（残留数据或手工创建）时既不能读也不能写，明确返回 409 而不是空内容或写入失败
+fn directory_conflict(note_path: &FsPath, note: &str) -> Option<Response> {
+    if !note_path.is_dir() {
+        return None;
+    }
+    warn!("note {note} path {note_path:?} is a directory");
+    Some((StatusCode::CONFLICT, "note path is occupied by a directory").into_response())
+}
+
+// 对外访问地址前缀：优先 PUBLIC_URL，否则按 Host 推断，再加上 BASE_PATH；
+// 开启 TRUST_PROXY_HEADERS 时先看反向代理转发的 X-Forwarded-Proto/X-Forwarded-Host
+fn public_base(state: &AppState, headers: &HeaderMap) -> String {
+    if let Some(url) = &state.public_url {
+        return url.to_string();
+    }
+    let first = |name| header_str(headers, name).and_then(|v| v.split(',').next()).map(str::trim).filter(|v| !v.is_empty());
+    let forwarded = |name| if state.trust_proxy_headers { first(name) } else { None };
+    let proto = forwarded("x-forwarded-proto").filter(|p| matches!(*p, "http" | "https")).unwrap_or("http");
+    let host = forwarded("x-forwarded-host")
+        .or_else(|| first("host"))
+        .filter(|h| h.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'-' | b':' | b'[' | b']')))
+        .unwrap_or("localhost");
+    format!("{proto}://{host}{}", state.base_path)
+}
+
+// 分享链接：编辑页、原文与只读页面地址
+async fn share_note(State(state): State<AppState>, Path(note): Path<String>, headers: HeaderMap) -> Response {
+    if !NOTE_RE.is_match(&note) {
+        return (StatusCode::BAD_REQUEST, INVALID_NOTE_ID).into_response();
+    }
+    let url = format!("{}/{note}", public_base(&state, &headers));
+    let mut resp = json_response(
+        StatusCode::OK,
+        &serde_json::json!({
+            "note": note,
+            "raw_url": format!("{url}?raw"),
+            "read_url": format!("{url}?view=read"),
+            "url": url,
+        }),
+    );
+    resp.headers_mut().extend(no_cache_headers(&state));
+    resp
+}
+
+// note 文件不存在且登记为别名时返回真实 note ID
+fn resolve_alias(state: &AppState, note: String) -> String {
+    if state.save_path.join(&note).is_file() {
+        return note;
+    }
+    meta::load(&state.save_path, &note).alias_of.unwrap_or(note)
+}
+
+// 从 static_root 读取同名文件；只接受单段文件名且扩展名在 STATIC_EXTENSIONS 内，文件不存在时返回 None
+fn serve_root_asset(state: &AppState, name: &str, req_headers: &HeaderMap) -> Option<Response> {
+    if !STATIC_NAME_RE.is_match(name) || name.contains("..") {
+        return None;
+    }
+    let ext = FsPath::new(name).extension()?.to_str()?.to_ascii_lowercase();
+    if !state.static_extensions.contains(&ext) {
+        return None;
+    }
+    // 关闭 PWA 时 sw.js 返回 404，已注册的 service worker 会随之注销
+    if name == "sw.js" && state.pwa_manifest.is_none() {
+        return None;
+    }
+    let path = state.static_root.join(name);
+    if !path.is_file() {
+        return None;
+    }
+    let mut resp = static_asset_response(&path, req_headers);
+    if resp.status().is_success() {
+        resp.headers_mut().extend(no_cache_headers(state));
+    }
+    Some(resp)
+}
+
+async fn get_manifest(State(state): State<AppState>) -> Response {
+    match &state.pwa_manifest {
+        Some(manifest) => {
+            ([("content-type", "application/manifest+json")], manifest.as_str().to_owned()).into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+// 样式覆盖文件：内容可能随时被运维修改，用 ETag 协商缓存而不是固定 max-age
+async fn serve_custom_css(State(state): State<AppState>, req_headers: HeaderMap) -> Response {
+    let Some(path) = &state.custom_css else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let bytes = match fs::read(path.as_path()) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("read CUSTOM_CSS_PATH {path:?} error: {e}");
+            return StatusCode::NOT_FOUND.into_response();
+        }
+    };
+    let etag = content_etag(&bytes);
+    let headers = [
+        ("content-type", "text/css; charset=utf-8".to_string()),
+        ("cache-control", "no-cache".to_string()),
+        ("etag", etag.clone()),
+    ];
+    if header_str(&req_headers, "if-none-match").is_some_and(|v| etag_matches(v, Some(&etag))) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+    (headers, bytes).into_response()
+}
+
+async fn serve_public_js(
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+    req_headers: HeaderMap,
+) -> impl IntoResponse {
+    let safe = file.replace("../", "");
+    let path = state.static_root.join("public").join("js").join(safe);
+    static_asset_response(&path, &req_headers)
+}
+
+// 静态资源：若存在预压缩的 .br/.gz 同名文件且客户端接受该编码，直接返回压缩版本
+fn static_asset_response(path: &FsPath, req_headers: &HeaderMap) -> Response {
+    let accept = req_headers
+        .get("accept-encoding")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let variant = [("br", "br"), ("gzip", "gz")]
+        .into_iter()
+        .filter(|(encoding, _)| accepts_encoding(accept, encoding))
+        .map(|(encoding, ext)| {
+            let mut p = path.as_os_str().to_owned();
+            p.push(".");
+            p.push(ext);
+            (encoding, PathBuf::from(p))
+        })
+        .find(|(_, p)| p.is_file());
+
+    let (encoding, file) = match &variant {
+        Some((encoding, p)) => (Some(*encoding), p.as_path()),
+        None => (None, path),
+    };
+    match fs::read(file) {
+        Ok(bytes) => {
+            let mime = mime_guess::from_path(path).first_or_octet_stream();
+            let mut headers = HeaderMap::new();
+            headers.insert("content-type", content_type_value(mime.as_ref(), path));
+            headers.insert("vary", HeaderValue::from_static("Accept-Encoding"));
+            if let Some(encoding) = encoding {
+                headers.insert("content-encoding", HeaderValue::from_static(encoding));
+            }
+            let mut resp = Response::builder().status(StatusCode::OK).body(bytes.into()).unwrap();
+            resp.headers_mut().extend(headers);
+            resp
+        }
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+// ?mime= 只允许白名单中的类型，不能借此以 text/html 等形式输出 note；
+// 不在白名单中的取值退回安全的 text/plain
+fn raw_content_type(mime: Option<&str>, lang: Option<&str>) -> &'static str {
+    match mime {
+        None => meta::lang_content_type(lang),
+        Some(m) => RAW_MIME_ALLOWLIST
+            .iter()
+            .find(|(name, _)| *name == m.trim())
+            .map_or(RAW_MIME_ALLOWLIST[0].1, |(_, ct)| ct),
+    }
+}
+
+// 推断出的类型不是合法的头部值时退回 application/octet-stream，不因个别文件 panic
+fn content_type_value(mime: &str, path: &FsPath) -> HeaderValue {
+    HeaderValue::from_str(mime).unwrap_or_else(|_| {
+        warn!("invalid content type {mime:?} for {path:?}, using application/octet-stream");
+        HeaderValue::from_static("application/octet-stream")
+    })
+}
+
+// 解析 Accept-Encoding，忽略 q=0 的编码；没有单独列出的编码按 * 的设置处理
+fn accepts_encoding(accept: &str, encoding: &str) -> bool {
+    let mut wildcard = false;
+    for item in accept.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let rejected = parts.any(|p| {
+            p.trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        if name.eq_ignore_ascii_case(encoding) {
+            return !rejected;
+        }
+        if name == "*" {
+            wildcard = !rejected;
+        }
+    }
+    wildcard
+}
+
+// 列出上传的 zip 压缩包内容（只读中央目录，不解压、不落盘）
+async fn list_tmp_archive(State(state): State<AppState>, Path(file): Path<String>) -> Response {
+    if file.contains(['/', '\\']) || file.starts_with('.') || !is_upload_name(&file) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let _slot = match acquire_file_slot(&state) {
+        Ok(slot) => slot,
+        Err(busy) => return busy.into_response(),
+    };
+    let Ok(f) = fs::File::open(state.save_path.join(&file)) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let mut archive = match zip::ZipArchive::new(io::BufReader::new(f)) {
+        Ok(a) => a,
+        Err(_) => return (StatusCode::BAD_REQUEST, "not a zip archive").into_response(),
+    };
+    let total = archive.len();
+    let mut entries = Vec::new();
+    for i in 0..total.min(MAX_ARCHIVE_ENTRIES) {
+        let entry = match archive.by_index_raw(i) {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("archive {file} entry {i} error: {e}");
+                return (StatusCode::BAD_REQUEST, "corrupt zip archive").into_response();
+            }
+        };
+        entries.push(serde_json::json!({
+            "name": entry.name(),
+            "size": entry.size(),
+            "compressed_size": entry.compressed_size(),
+            "dir": entry.is_dir(),
+        }));
+    }
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({
+            "file": file,
+            "total": total,
+            "truncated": total > MAX_ARCHIVE_ENTRIES,
+            "entries": entries,
+        }),
+    )
+}
+
+// 占用一个文件读取名额；名额已满时返回 503，由客户端稍后重试
+type FileSlotsBusy = (StatusCode, [(&'static str, &'static str); 2], &'static str);
+
+fn acquire_file_slot(state: &AppState) -> Result<Option<ratelimit::FileSlot>, FileSlotsBusy> {
+    let Some(slots) = &state.file_slots else {
+        return Ok(None);
+    };
+    slots
+        .acquire()
+        .map(Some)
+        .ok_or((StatusCode::SERVICE_UNAVAILABLE, [("retry-after", "1"), ("cache-control", "no-store")], "too many concurrent file reads"))
+}
+
+async fn serve_tmp_file(
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    // 不允许访问子目录及隐藏文件（如 .meta）；上传与 note 共用 save_path，只返回上传文件名格式的文件，
+    // 避免 note 经由这里以长缓存、宽松 CORS 的方式被读出
+    if file.contains(['/', '\\']) || file.starts_with('.') || !is_upload_name(&file) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    // 名字同时是合法 note ID 时无法区分是上传还是 note：计入 READ_RATE_LIMIT，且不缓存
+    let maybe_note = NOTE_RE.is_match(&file);
+    if maybe_note {
+        if let Err(retry_after) = check_read_limit(&state, remote, 1) {
+            return too_many_requests(retry_after);
+        }
+    }
+    let safe = file.replace("../", "");
+    let path = state.save_path.join(&safe);
+    // 打开文件前先占名额，名额随响应体一起释放
+    let slot = match acquire_file_slot(&state) {
+        Ok(slot) => slot,
+        Err(busy) => return busy.into_response(),
+    };
+    let Ok(f) = tokio::fs::File::open(&path).await else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let len = match f.metadata().await {
+        Ok(meta) if meta.is_file() => meta.len(),
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let mime = meta::load_upload_type(&state.save_path, &safe)
+        .unwrap_or_else(|| mime_guess::from_path(&path).first_or_octet_stream().to_string());
+    let body = Body::from_stream(ReaderStream::new(ratelimit::SlotReader::new(f, slot)));
+    let mut resp = Response::builder().status(StatusCode::OK).body(body).unwrap();
+    resp.headers_mut().insert("content-type", content_type_value(&mime, &path));
+    resp.headers_mut().insert("content-length", HeaderValue::from(len));
+    if maybe_note {
+        resp.headers_mut().extend(no_cache_headers(&state));
+    }
+    resp
+}
+
+// 占用一个 MAX_UPLOADS_PER_IP 名额；普通上传与分块上传的 init、分块、complete 请求共用
+fn acquire_upload_slot(state: &AppState, remote: SocketAddr) -> Result<Option<ratelimit::ConcurrencySlot>, UploadFailure> {
+    let Some(slots) = &state.upload_slots else {
+        return Ok(None);
+    };
+    slots
+        .acquire(remote.ip())
+        .map(Some)
+        .ok_or((StatusCode::TOO_MANY_REQUESTS, "too_many_concurrent_uploads", Some(slots.max())))
+}
+
+async fn upload_file(
+    State(state): State<AppState>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    // /upload 保留路由，避免落到 /:note 被当成 note 写入
+    if !state.uploads_enabled {
+        return upload_error(StatusCode::FORBIDDEN, "uploads_disabled", None);
+    }
+    // 名额在请求体读完（无论成功失败）、处理函数返回时随 _slot 一起释放
+    let _slot = match acquire_upload_slot(&state, remote) {
+        Ok(slot) => slot,
+        Err((status, reason, limit)) => return upload_error(status, reason, limit),
+    };
+    // 客户端重试时带相同的 Idempotency-Key，返回第一次成功上传的结果而不是再存一份；
+    // 按客户端 IP 区分，别的客户端用了同一个 key 既拿不到这次的结果，也不会被当成重试
+    let key = header_str(&headers, "idempotency-key");
+    if key.is_some_and(|key| !idempotency::IdempotencyCache::valid_key(key)) {
+        return upload_error(StatusCode::BAD_REQUEST, "invalid_idempotency_key", None);
+    }
+    let key = key.map(|key| format!("{}|{key}", remote.ip()));
+    if let Some(key) = &key {
+        if let Some((status, json)) = state.upload_keys.get(key) {
+            let mut resp = json_response(status, &json);
+            resp.headers_mut().insert("idempotent-replayed", HeaderValue::from_static("true"));
+            return resp;
+        }
+    }
+    // 保存到 _tmp 下，文件名加时间戳避免冲突；可以一次上传多个 file 字段
+    let mut results = Vec::new();
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if let Some(name) = field.name().map(|s| s.to_string()) {
+            if name != "file" { continue; }
         }
 
         let file_name = field.file_name().map(|s| s.to_string()).unwrap_or_else(|| "upload.bin".to_string());
+        // 读取失败后请求体已无法继续解析：记下这个文件的失败，之前已保存的文件照常返回
         let data = match field.bytes().await {
             Ok(b) => b,
-            Err(_) => return (StatusCode::BAD_REQUEST, "invalid file").into_response(),
+            Err(e) => {
+                let failure = if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                    (StatusCode::FORBIDDEN, "file_too_large", Some(MAX_UPLOAD_SIZE))
+                } else {
+                    (StatusCode::BAD_REQUEST, "invalid_file", None)
+                };
+                results.push((file_name, Err(failure)));
+                break;
+            }
+        };
+        let result = if data.len() > MAX_UPLOAD_SIZE {
+            Err((StatusCode::FORBIDDEN, "file_too_large", Some(MAX_UPLOAD_SIZE)))
+        } else {
+            store_upload(&state, &file_name, UploadSource::Bytes(&data))
         };
-        if data.len() > MAX_SIZE { return (StatusCode::FORBIDDEN, "file too large").into_response(); }
+        results.push((file_name, result));
+    }
+
+    let stored_any = results.iter().any(|(_, result)| result.is_ok());
+    // 单个文件保持原有的响应格式；多个文件返回 207 及逐个文件的结果
+    let (status, json) = match results.len() {
+        0 => return upload_error(StatusCode::BAD_REQUEST, "no_file", None),
+        1 => match results.pop().unwrap().1 {
+            Ok(json) => (StatusCode::OK, json),
+            Err((status, reason, limit)) => return upload_error(status, reason, limit),
+        },
+        _ => {
+            let items: Vec<_> = results
+                .into_iter()
+                .map(|(file, result)| match result {
+                    Ok(mut item) => {
+                        item["file"] = file.into();
+                        item["ok"] = true.into();
+                        item
+                    }
+                    Err((_, reason, limit)) => serde_json::json!({
+                        "file": file,
+                        "ok": false,
+                        "error": reason,
+                        "message": upload_error_message(reason),
+                        "limit": limit,
+                    }),
+                })
+                .collect();
+            (StatusCode::MULTI_STATUS, serde_json::json!({ "results": items }))
+        }
+    };
+    // 只记录至少存下了一个文件的结果，全部失败时允许用同一个 key 重试
+    if let Some(key) = key.filter(|_| stored_any) {
+        state.upload_keys.insert(key, status, json.clone());
+    }
+    json_response(status, &json)
+}
+
+#[derive(Deserialize)]
+struct UploadInitForm {
+    name: Option<String>,
+    size: usize,
+}
+
+// 分块上传第一步：登记文件名与总大小，返回上传 ID
+async fn upload_init(
+    State(state): State<AppState>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Form(form): Form<UploadInitForm>,
+) -> Response {
+    if !state.uploads_enabled {
+        return upload_error(StatusCode::FORBIDDEN, "uploads_disabled", None);
+    }
+    let _slot = match acquire_upload_slot(&state, remote) {
+        Ok(slot) => slot,
+        Err((status, reason, limit)) => return upload_error(status, reason, limit),
+    };
+    if form.size == 0 {
+        return upload_error(StatusCode::BAD_REQUEST, "invalid_file", None);
+    }
+    if form.size > MAX_UPLOAD_SIZE {
+        return upload_error(StatusCode::FORBIDDEN, "file_too_large", Some(MAX_UPLOAD_SIZE));
+    }
+    let name = form.name.filter(|n| !n.is_empty()).unwrap_or_else(|| "upload.bin".to_string());
+    match state.chunked.init(name, form.size) {
+        Ok(id) => json_response(
+            StatusCode::CREATED,
+            &serde_json::json!({ "id": id, "offset": 0, "size": form.size, "url": format!("{}/upload/{id}", state.base_path) }),
+        ),
+        Err(e) => chunk_error_response(e),
+    }
+}
+
+// 查询已接收的字节数，断线后客户端据此续传（HEAD 同样可用）
+async fn upload_status(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    let Some((offset, size)) = state.chunked.status(&id) else {
+        return chunk_error_response(chunked::ChunkError::NotFound);
+    };
+    let mut resp = json_response(StatusCode::OK, &serde_json::json!({ "id": id, "offset": offset, "size": size }));
+    resp.headers_mut().insert("upload-offset", HeaderValue::from(offset));
+    resp.headers_mut().insert("upload-length", HeaderValue::from(size));
+    resp
+}
+
+// 追加一个分块：Upload-Offset 必须等于已接收的字节数
+async fn upload_chunk(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if !state.uploads_enabled {
+        return upload_error(StatusCode::FORBIDDEN, "uploads_disabled", None);
+    }
+    let _slot = match acquire_upload_slot(&state, remote) {
+        Ok(slot) => slot,
+        Err((status, reason, limit)) => return upload_error(status, reason, limit),
+    };
+    let Some(offset) = header_str(&headers, "upload-offset").and_then(|v| v.trim().parse().ok()) else {
+        return (StatusCode::BAD_REQUEST, "missing or invalid Upload-Offset").into_response();
+    };
+    // 写盘是阻塞操作，放到 blocking 线程池
+    let chunked = state.chunked.clone();
+    let appended = tokio::task::spawn_blocking(move || chunked.append(&id, offset, &body)).await;
+    match appended.unwrap_or_else(|e| Err(io::Error::other(e).into())) {
+        Ok(received) => {
+            let mut resp = StatusCode::NO_CONTENT.into_response();
+            resp.headers_mut().insert("upload-offset", HeaderValue::from(received));
+            resp
+        }
+        Err(e) => chunk_error_response(e),
+    }
+}
+
+// 全部分块到齐后转存到 save_path，返回与普通上传相同的结果
+async fn upload_complete(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+) -> Response {
+    if !state.uploads_enabled {
+        return upload_error(StatusCode::FORBIDDEN, "uploads_disabled", None);
+    }
+    let _slot = match acquire_upload_slot(&state, remote) {
+        Ok(slot) => slot,
+        Err((status, reason, limit)) => return upload_error(status, reason, limit),
+    };
+    let (name, part) = match state.chunked.finish(&id) {
+        Ok(finished) => finished,
+        Err(e) => return chunk_error_response(e),
+    };
+    let stored = tokio::task::spawn_blocking(move || {
+        let result = store_upload(&state, &name, UploadSource::File(&part));
+        // 没能改名到位（如超出 FILE_LIMIT）时丢弃临时文件
+        let _ = fs::remove_file(&part);
+        result
+    })
+    .await;
+    upload_response(stored.unwrap_or(Err((StatusCode::INTERNAL_SERVER_ERROR, "write_failed", None))))
+}
+
+fn chunk_error_response(e: chunked::ChunkError) -> Response {
+    use chunked::ChunkError;
+    let (status, reason, offset) = match e {
+        ChunkError::NotFound => (StatusCode::NOT_FOUND, "upload_not_found", None),
+        ChunkError::OffsetMismatch(offset) => (StatusCode::CONFLICT, "offset_mismatch", Some(offset)),
+        ChunkError::Incomplete(offset) => (StatusCode::CONFLICT, "incomplete", Some(offset)),
+        ChunkError::TooLarge => (StatusCode::FORBIDDEN, "file_too_large", None),
+        ChunkError::TooManyPending => (StatusCode::SERVICE_UNAVAILABLE, "too_many_uploads", None),
+        ChunkError::Io(e) => {
+            error!("chunked upload error: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "write_failed", None)
+        }
+    };
+    let mut resp = upload_error(status, reason, None);
+    if let Some(offset) = offset {
+        resp.headers_mut().insert("upload-offset", HeaderValue::from(offset));
+    }
+    resp
+}
+
+// 把上传内容保存为 "<时间戳>_<文件名>"，返回前端插入用的地址；普通上传与分块上传共用
+// 失败时为 (状态码, 原因, 相关上限)
+type UploadFailure = (StatusCode, &'static str, Option<usize>);
+
+// 上传内容：普通上传已在内存中；分块上传是 .meta/_partial 下拼好的临时文件
+#[derive(Clone, Copy)]
+enum UploadSource<'a> {
+    Bytes(&'a [u8]),
+    File(&'a FsPath),
+}
+
+fn store_upload(state: &AppState, file_name: &str, source: UploadSource) -> Result<serde_json::Value, UploadFailure> {
+    // 上传文件与 note 共用 save_path，同样受 FILE_LIMIT 约束
+    match current_file_count(state) {
+        Ok(count) if count >= state.file_limit => {
+            record_file_limit(state, count);
+            return Err((StatusCode::FORBIDDEN, "file_limit_reached", Some(state.file_limit)));
+        }
+        Ok(_) => {}
+        Err(e) => error!("count files error: {e}"),
+    }
 
-        let ext = std::path::Path::new(&file_name).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
-        let ts = chrono_like_timestamp();
-        let safe_name = sanitize_filename(&file_name);
-        let stored = format!("{ts}_{safe_name}");
-        let path = state.save_path.join(&stored);
+    let ext = std::path::Path::new(file_name).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    let ts = chrono_like_timestamp();
+    let safe_name = sanitize_filename(file_name, &state.sanitize);
+    // 同一秒内上传同名文件时加序号，避免互相覆盖
+    let mut stored = format!("{ts}_{safe_name}");
+    let mut n = 1;
+    while state.save_path.join(&stored).exists() {
+        stored = format!("{ts}_{n}_{safe_name}");
+        n += 1;
+    }
+    let path = state.save_path.join(&stored);
 
-        if let Err(e) = fs::write(&path, &data) {
+    let written = match source {
+        UploadSource::Bytes(data) => fs::write(&path, data).map(|_| data.len() as u64),
+        // 临时文件与 save_path 在同一文件系统，直接改名到位，不整体读入内存
+        UploadSource::File(part) => fs::rename(part, &path).and_then(|_| fs::metadata(&path)).map(|m| m.len()),
+    };
+    let size = match written {
+        Ok(size) => size,
+        Err(e) => {
             error!("upload write error: {e}");
-            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "write_failed", None));
+        }
+    };
+    state.stats.record_write(None, size);
+    state.metrics.upload_bytes.observe(size);
+    if state.max_tmp_files > 0 {
+        evict_old_uploads(state);
+    }
+
+    // 按内容探测类型并记录下来，文件名没有扩展名时也能以正确类型返回
+    let head = match source {
+        UploadSource::Bytes(data) => Cow::Borrowed(data),
+        UploadSource::File(_) => Cow::Owned(read_head(&path, 32)),
+    };
+    let sniffed = sniff_content_type(&head);
+    if let Some(mime) = sniffed {
+        if let Err(e) = meta::save_upload_type(&state.save_path, &stored, mime) {
+            error!("upload type write error: {e}");
         }
+    }
+    backup(state, &stored);
 
-        // 返回相对路径供前端插入 `_tmp/<name>`
-        let is_image = matches!(ext.as_str(), "png" | "jpg" | "jpeg" | "gif" | "webp" | "bmp" | "svg");
-        let url = format!("/_tmp/{}", stored);
-        let json = serde_json::json!({
-            "url": url,
-            "is_image": is_image,
-            "name": stored,
-        });
-        let resp = Response::builder()
-            .status(StatusCode::OK)
-            .header("content-type", "application/json")
-            .body(serde_json::to_vec(&json).unwrap().into())
-            .unwrap();
-        return resp;
+    // 返回相对路径供前端插入 `_tmp/<name>`
+    let is_image = sniffed.is_some_and(|m| m.starts_with("image/"))
+        || matches!(ext.as_str(), "png" | "jpg" | "jpeg" | "gif" | "webp" | "bmp" | "svg");
+    let url = format!("{}/_tmp/{}", state.base_path, utf8_percent_encode(&stored, PATH_SEGMENT));
+    let mut json = serde_json::json!({
+        "url": url,
+        "is_image": is_image,
+        "name": stored,
+        "display_name": display_name(&safe_name, state.upload_name_length),
+    });
+    // 只解析文件头获取图片尺寸，供前端插入时指定宽高；解析失败不影响上传
+    if is_image {
+        let dims = match source {
+            UploadSource::Bytes(data) => imagesize::blob_size(data),
+            UploadSource::File(_) => imagesize::size(&path),
+        };
+        if let Ok(size) = dims {
+            json["width"] = size.width.into();
+            json["height"] = size.height.into();
+        }
+    }
+    Ok(json)
+}
+
+fn upload_response(result: Result<serde_json::Value, UploadFailure>) -> Response {
+    match result {
+        Ok(json) => json_response(StatusCode::OK, &json),
+        Err((status, reason, limit)) => upload_error(status, reason, limit),
+    }
+}
+
+// 是否符合上传文件名 "<时间戳>_<文件名>" 的格式（可能同时也是合法 note ID）
+fn is_upload_name(name: &str) -> bool {
+    name.split_once('_')
+        .is_some_and(|(ts, rest)| !ts.is_empty() && ts.bytes().all(|b| b.is_ascii_digit()) && !rest.is_empty())
+}
+
+// 上传文件名为 "<时间戳>_<文件名>"。与合法 note ID 无法区分的名字（如无扩展名的
+// "1700000000_file"）一律不计入、不删除，宁可少删也不误删 note
+fn upload_timestamp(name: &str) -> Option<u64> {
+    let (ts, rest) = name.split_once('_')?;
+    if rest.is_empty() || NOTE_RE.is_match(name) {
+        return None;
+    }
+    ts.parse().ok()
+}
+
+// 上传数量超过 MAX_TMP_FILES 时按时间戳从旧到新删除
+fn evict_old_uploads(state: &AppState) {
+    let entries = match fs::read_dir(state.save_path.as_path()) {
+        Ok(rd) => rd,
+        Err(e) => {
+            error!("list uploads error: {e}");
+            return;
+        }
+    };
+    let mut uploads: Vec<(u64, String)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            let ts = upload_timestamp(&name)?;
+            entry.metadata().ok().filter(|m| m.is_file())?;
+            Some((ts, name))
+        })
+        .collect();
+    if uploads.len() <= state.max_tmp_files {
+        return;
+    }
+    uploads.sort();
+    let excess = uploads.len() - state.max_tmp_files;
+    let mut evicted = 0;
+    for (_, name) in uploads.into_iter().take(excess) {
+        let path = state.save_path.join(&name);
+        let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                state.stats.record_remove(size);
+                meta::remove_upload_type(&state.save_path, &name);
+                backup(state, &name);
+                evicted += 1;
+            }
+            Err(e) => error!("evict upload {name:?} error: {e}"),
+        }
+    }
+    info!("evicted {evicted} old uploads (MAX_TMP_FILES={})", state.max_tmp_files);
+}
+
+// 上传失败时返回结构化错误，前端据 reason 给出具体提示
+fn upload_error(status: StatusCode, reason: &str, limit: Option<usize>) -> Response {
+    json_response(
+        status,
+        &serde_json::json!({ "error": reason, "message": upload_error_message(reason), "limit": limit }),
+    )
+}
+
+fn upload_error_message(reason: &str) -> &'static str {
+    match reason {
+        "file_too_large" => "file too large",
+        "file_limit_reached" => "file limit reached",
+        "invalid_file" => "invalid file",
+        "no_file" => "no file",
+        "uploads_disabled" => "uploads are disabled",
+        "upload_not_found" => "upload not found or expired",
+        "offset_mismatch" => "chunk offset does not match received bytes",
+        "incomplete" => "upload is incomplete",
+        "too_many_uploads" => "too many uploads in progress",
+        "too_many_concurrent_uploads" => "too many uploads in progress from this client",
+        "invalid_idempotency_key" => "Idempotency-Key must be 1-128 visible ASCII characters",
+        _ => "upload failed",
+    }
+}
+
+// 按 Content-Encoding 解码请求体，目前支持 gzip；解压时超过 max 立即中止
+fn decode_body<'a>(headers: &HeaderMap, body: &'a [u8], max: usize) -> Result<Cow<'a, [u8]>, (StatusCode, &'static str)> {
+    let encoding = headers
+        .get("content-encoding")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("identity")
+        .trim()
+        .to_ascii_lowercase();
+    match encoding.as_str() {
+        "" | "identity" => Ok(Cow::Borrowed(body)),
+        "gzip" | "x-gzip" => {
+            let mut out = Vec::new();
+            let mut decoder = GzDecoder::new(body).take(max as u64 + 1);
+            if decoder.read_to_end(&mut out).is_err() {
+                return Err((StatusCode::BAD_REQUEST, "invalid gzip body"));
+            }
+            if out.len() > max {
+                return Err((StatusCode::PAYLOAD_TOO_LARGE, "decompressed body too large"));
+            }
+            Ok(Cow::Owned(out))
+        }
+        _ => Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported content-encoding")),
+    }
+}
+
+// note 内容的强 ETag（FNV-1a 64 位）
+fn content_etag(data: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in data {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("\"{hash:016x}\"")
+}
+
+// If-Match 的取值可以是 * 或逗号分隔的 ETag 列表；note 不存在时不匹配
+fn etag_matches(header: &str, current: Option<&str>) -> bool {
+    let Some(current) = current else { return false };
+    header.split(',').map(str::trim).any(|tag| tag == "*" || tag == current)
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+// 非法 note ID：默认跳转到随机 note（对浏览器友好）；
+// 开启 STRICT_NOTE_IDS 或客户端只接受 JSON 时返回 400，便于脚本/API 客户端处理
+const INVALID_NOTE_ID: &str = "invalid note id: expected 1-64 characters from [a-zA-Z0-9_-]";
+
+fn invalid_note_response(state: &AppState, headers: &HeaderMap) -> Response {
+    let accept = headers
+        .get("accept")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let api_client = accept.contains("application/json") && !accept.contains("text/html");
+    if state.strict_note_ids || api_client {
+        return (StatusCode::BAD_REQUEST, INVALID_NOTE_ID).into_response();
+    }
+    Redirect::to(&format!("{}/{}", state.base_path, new_note_id(state))).into_response()
+}
+
+fn is_cli_client(headers: &HeaderMap) -> bool {
+    let ua = headers
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    ua.starts_with("curl") || ua.starts_with("Wget")
+}
+
+// 读取文件开头最多 n 字节，读取失败时返回空
+fn read_head(path: &FsPath, n: u64) -> Vec<u8> {
+    let mut head = Vec::new();
+    if let Ok(file) = fs::File::open(path) {
+        let _ = file.take(n).read_to_end(&mut head);
+    }
+    head
+}
+
+// 根据文件头识别常见格式
+fn sniff_content_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if data.starts_with(b"BM") && data.len() >= 26 {
+        Some("image/bmp")
+    } else if data.starts_with(b"%PDF-") {
+        Some("application/pdf")
+    } else if data.starts_with(b"PK\x03\x04") {
+        Some("application/zip")
+    } else {
+        None
+    }
+}
+
+// 删除超过 NOTE_MAX_IDLE_DAYS 未修改/未读取的 note；置顶和冻结的 note 不删除
+fn reap_idle_notes(state: &AppState) -> io::Result<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let max_idle = state.note_max_idle_days.saturating_mul(86400);
+    // PINNED_NOTES 中的别名保护的是它指向的 note
+    let pinned: Vec<String> = state.pinned_notes.iter().map(|n| resolve_alias(state, n.clone())).collect();
+    let mut reaped = 0;
+    for entry in fs::read_dir(state.save_path.as_path())?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !NOTE_RE.is_match(&name) || pinned.contains(&name) {
+            continue;
+        }
+        let Ok(file) = entry.metadata() else { continue };
+        if !file.is_file() {
+            continue;
+        }
+        let modified = modified_secs(&file);
+        let last = state.access_times.as_ref().and_then(|t| t.get(&name)).map_or(modified, |a| a.max(modified));
+        if now.saturating_sub(last) <= max_idle || meta::load(&state.save_path, &name).immutable {
+            continue;
+        }
+        match delete_note(state, &name) {
+            Ok(Some(old)) => {
+                info!("reaped idle note {name} (idle {} days)", now.saturating_sub(last) / 86400);
+                // 删掉 sidecar，之后重新创建的同名 note 不继承旧的语言、描述等设置
+                if let Err(e) = meta::save(&state.save_path, &name, &meta::NoteMeta::default()) {
+                    error!("reap idle note {name} meta error: {e}");
+                }
+                if let Some(log) = &state.audit_log {
+                    log.record("reap", &name, None, old);
+                }
+                reaped += 1;
+            }
+            Ok(None) => {}
+            Err(e) => error!("reap idle note {name} error: {e}"),
+        }
+    }
+    if reaped > 0 {
+        info!("idle note cleanup removed {reaped} notes");
     }
-
-    (StatusCode::BAD_REQUEST, "no file").into_response()
+    Ok(())
 }
 
 fn chrono_like_timestamp() -> String {
@@ -304,23 +3433,223 @@ fn chrono_like_timestamp() -> String {
     now.to_string()
 }
 
-fn sanitize_filename(name: &str) -> String {
-    let mut s = name.replace(['\\', '/', ':', '*', '?', '"', '<', '>', '|'], "_");
-    if s.is_empty() { s = "file".to_string(); }
+// 上传文件名的清洗规则
+struct SanitizeOptions {
+    disallowed: Vec<char>,
+    fallback: String,
+    // true: 非法字符按 %XX 编码保留原名；false: 替换为 "_"（连续的合并为一个）
+    percent_encode: bool,
+}
+
+impl Default for SanitizeOptions {
+    fn default() -> Self {
+        SanitizeOptions {
+            disallowed: DEFAULT_SANITIZE_CHARS.chars().collect(),
+            fallback: "file".to_string(),
+            percent_encode: false,
+        }
+    }
+}
+
+// 插入链接时显示的文件名：超过 max 个字符时截断主名并加省略号，保留扩展名；0 表示不截断
+fn display_name(name: &str, max: usize) -> String {
+    let len = name.chars().count();
+    if max == 0 || len <= max {
+        return name.to_string();
+    }
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && ext.chars().count() + 2 < max => (stem, Some(ext)),
+        _ => (name, None),
+    };
+    let keep = max - 1 - ext.map_or(0, |e| e.chars().count() + 1);
+    let mut out: String = stem.chars().take(keep).collect();
+    out.push('…');
+    if let Some(ext) = ext {
+        out.push('.');
+        out.push_str(ext);
+    }
+    out
+}
+
+fn sanitize_filename(name: &str, opts: &SanitizeOptions) -> String {
+    let mut s = String::with_capacity(name.len());
+    let mut last_replaced = false;
+    for c in name.chars() {
+        // 控制字符总是视为非法；percent 模式下 "%" 也需编码以免产生歧义
+        let bad = c.is_control() || opts.disallowed.contains(&c) || (opts.percent_encode && c == '%');
+        if !bad {
+            s.push(c);
+            last_replaced = false;
+        } else if opts.percent_encode {
+            let mut buf = [0u8; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                s.push_str(&format!("%{b:02X}"));
+            }
+        } else if !last_replaced {
+            s.push('_');
+            last_replaced = true;
+        }
+    }
+    // 只剩占位符（如名字全是非法字符）时使用回退名
+    if s.trim_matches(|c| c == '_' || c == '.').is_empty() {
+        s = opts.fallback.clone();
+    }
     s
 }
 
-fn no_cache_headers() -> HeaderMap {
+// DEBUG_TIMING：响应头 X-Response-Time-Ms 给出处理耗时（到响应头生成为止，流式响应体的传输时间不计入）
+async fn response_time(req: axum::extract::Request, next: axum::middleware::Next) -> Response {
+    let start = std::time::Instant::now();
+    let mut resp = next.run(req).await;
+    let ms = format!("{:.3}", start.elapsed().as_secs_f64() * 1000.0);
+    if let Ok(v) = HeaderValue::from_str(&ms) {
+        resp.headers_mut().insert("x-response-time-ms", v);
+    }
+    resp
+}
+
+// ?pretty=1 或 PRETTY_JSON 时 JSON 接口输出带缩进的格式，?pretty=0 可单次关闭；
+// 中间件只记录本次请求的选择，由 json_response 读取，不改写响应体，note 内容原样输出
+async fn pretty_json(default: bool, req: axum::extract::Request, next: axum::middleware::Next) -> Response {
+    let pretty = req
+        .uri()
+        .query()
+        .and_then(|q| {
+            q.split('&').find_map(|pair| match pair.split_once('=') {
+                Some(("pretty", v)) => Some(!matches!(v, "0" | "false")),
+                None if pair == "pretty" => Some(true),
+                _ => None,
+            })
+        })
+        .unwrap_or(default);
+    PRETTY_JSON.scope(pretty, next.run(req)).await
+}
+
+tokio::task_local! {
+    static PRETTY_JSON: bool;
+}
+
+fn json_response(status: StatusCode, value: &serde_json::Value) -> Response {
+    let body = if PRETTY_JSON.try_with(|p| *p).unwrap_or(false) {
+        let mut out = serde_json::to_vec_pretty(value).unwrap();
+        out.push(b'\n');
+        out
+    } else {
+        serde_json::to_vec(value).unwrap()
+    };
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body.into())
+        .unwrap()
+}
+
+fn modified_secs(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// API 中的修改时间：modified 为 RFC3339 UTC，配置了 TZ/TIME_FORMAT 时另加本地化的 modified_human
+fn set_modified(state: &AppState, item: &mut serde_json::Value, secs: u64) {
+    let Some(utc) = chrono::DateTime::from_timestamp(secs as i64, 0) else {
+        return;
+    };
+    item["modified"] = utc.to_rfc3339_opts(chrono::SecondsFormat::Secs, true).into();
+    if let Some(format) = &state.time_format {
+        item["modified_human"] = utc.with_timezone(&chrono::Local).format(format).to_string().into();
+    }
+}
+
+// LEGACY_CACHE_HEADERS=false 时只发送 Cache-Control，省掉 HTTP/1.0 时代的 Pragma/Expires
+fn no_cache_headers(state: &AppState) -> HeaderMap {
     let mut h = HeaderMap::new();
     h.insert(
         "Cache-Control",
         HeaderValue::from_static("no-cache, no-store, must-revalidate"),
     );
-    h.insert("Pragma", HeaderValue::from_static("no-cache"));
-    h.insert("Expires", HeaderValue::from_static("0"));
+    if state.legacy_cache_headers {
+        h.insert("Pragma", HeaderValue::from_static("no-cache"));
+        h.insert("Expires", HeaderValue::from_static("0"));
+    }
     h
 }
 
+fn record_file_limit(state: &AppState, count: usize) {
+    warn!("File limit reached: {count} files, limit {}", state.file_limit);
+    Metrics::inc(&state.metrics.file_limit_reached);
+}
+
+// REJECT_SHORT_NOTE_IDS 拒绝新建短 ID 的 note：400，附带最小长度，前端据此提示
+fn short_id_response(min: usize) -> Response {
+    json_response(
+        StatusCode::BAD_REQUEST,
+        &serde_json::json!({
+            "error": "note_id_too_short",
+            "min_length": min,
+            "message": format!(
+                "new notes need an ID of at least {min} characters; open / for a random ID or pick a longer name"
+            ),
+        }),
+    )
+}
+
+// 507 Insufficient Storage，附带机器可读的原因，便于客户端提示与运维告警
+fn file_limit_response(state: &AppState, count: usize) -> Response {
+    record_file_limit(state, count);
+    json_response(
+        StatusCode::INSUFFICIENT_STORAGE,
+        &serde_json::json!({ "error": "file_limit_reached", "limit": state.file_limit, "count": count }),
+    )
+}
+
+// 删除 note 文件；删除成功时返回删除前的文件大小，不存在时返回 None
+fn delete_note(state: &AppState, note: &str) -> io::Result<Option<u64>> {
+    let path = state.save_path.join(note);
+    let old = match fs::metadata(&path) {
+        Ok(meta) if meta.is_file() => meta.len(),
+        Ok(_) => return Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    match fs::remove_file(&path) {
+        Ok(()) => {
+            state.stats.record_remove(old);
+            let mut note_meta = meta::load(&state.save_path, note);
+            if note_meta.content_hash.take().is_some() {
+                let _ = meta::save(&state.save_path, note, &note_meta);
+            }
+            backup(state, note);
+            Ok(Some(old))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn audit(state: &AppState, event: &str, note: &str, remote: SocketAddr, bytes: u64) {
+    if let Some(log) = &state.audit_log {
+        log.record(event, note, Some(remote.ip()), bytes);
+    }
+}
+
+// 在主目录写入/删除完成后调用，把该文件的现状镜像到 BACKUP_PATH
+fn backup(state: &AppState, name: &str) {
+    if let Some(backup) = &state.backup {
+        backup.mirror(name);
+    }
+}
+
+// 缓存计数就绪前回退到目录扫描
+fn current_file_count(state: &AppState) -> io::Result<usize> {
+    match state.stats.file_count() {
+        Some(count) => Ok(count),
+        None => count_files_in_dir(&state.save_path),
+    }
+}
+
 fn count_files_in_dir(dir: &FsPath) -> io::Result<usize> {
     let mut count = 0usize;
     for entry in fs::read_dir(dir)? {
@@ -333,10 +3662,10 @@ fn count_files_in_dir(dir: &FsPath) -> io::Result<usize> {
     Ok(count)
 }
 
-fn generate_excerpt_by_path(path: &FsPath) -> String {
+fn generate_excerpt_by_path(path: &FsPath, length: usize) -> String {
     if path.is_file() {
-        if let Ok(s) = fs::read_to_string(path) {
-            return generate_excerpt(&s, 150);
+        if let Ok(s) = store::read_note_string(path) {
+            return generate_excerpt(&s, length);
         }
     }
     String::new()
@@ -350,12 +3679,40 @@ fn generate_excerpt(text: &str, length: usize) -> String {
     s
 }
 
-fn random_note_id(len: usize) -> String {
+// 生成一个尚未被占用（既不是 note 也不是别名）的随机 ID；
+// 每次冲突都会计数，冲突频繁说明 ID 空间趋于拥挤，应调大 NOTE_ID_LENGTH
+fn unused_note_id(state: &AppState) -> Option<String> {
+    for attempt in 0..state.note_id_retries {
+        let id = random_note_id(&state.random_alphabet, state.note_id_length);
+        let taken = RESERVED_NOTE_IDS.contains(&id.as_str())
+            || state.save_path.join(&id).exists()
+            || meta::load(&state.save_path, &id).alias_of.is_some();
+        if !taken {
+            return Some(id);
+        }
+        Metrics::inc(&state.metrics.note_id_collisions);
+        if attempt + 1 == state.note_id_retry_warn {
+            warn!(
+                "note id collided {} times in a row (length {}), consider increasing NOTE_ID_LENGTH",
+                attempt + 1,
+                state.note_id_length
+            );
+        }
+    }
+    None
+}
+
+// 重试耗尽时退回普通随机 ID
+fn new_note_id(state: &AppState) -> String {
+    unused_note_id(state).unwrap_or_else(|| random_note_id(&state.random_alphabet, state.note_id_length))
+}
+
+fn random_note_id(alphabet: &[u8], len: usize) -> String {
     let mut rng = rand::thread_rng();
     (0..len)
         .map(|_| {
-            let idx = rng.gen_range(0..RANDOM_ALPHABET.len());
-            RANDOM_ALPHABET[idx] as char
+            let idx = rng.gen_range(0..alphabet.len());
+            alphabet[idx] as char
         })
         .collect()
 }
@@ -369,7 +3726,88 @@ fn html_escape(input: &str) -> String {
         .replace("'", "&#39;")
 }
 
-fn render_html(note: &str, content_escaped: &str, excerpt: &str) -> String {
+// 编辑页的实例/note 级选项
+struct PageOptions<'a> {
+    // BASE_PATH，根路径部署时为空串
+    base: &'a str,
+    // 侧边栏中显示的置顶 note（已过滤掉不存在的）
+    pinned: &'a [String],
+    uploads_enabled: bool,
+    pwa: bool,
+    // 是否引用 /custom.css（CUSTOM_CSS_PATH）
+    custom_css: bool,
+    // 站点公告（已是可直接插入的 HTML）
+    notice: Option<&'a str>,
+    insert_format: InsertFormat,
+    immutable: bool,
+    // 设置了 EDIT_TOKEN 而访客没有编辑权限：只读，并提供输入令牌的入口
+    locked: bool,
+    // 内容超过 EDITOR_DISPLAY_LIMIT 被截断：编辑框只读，直到加载完整内容
+    truncated: bool,
+    // 该 note 不希望被搜索引擎收录
+    noindex: bool,
+    // 打开时默认显示渲染后的 markdown（仍可手动切换回编辑）
+    render: bool,
+}
+
+fn render_html(note: &str, content_escaped: &str, excerpt: &str, opts: &PageOptions) -> String {
+    let upload_link = if opts.uploads_enabled {
+        r##"<a href="#" id="uploadTrigger">&nbsp; | &nbsp;⤴ upload</a>"##
+    } else {
+        ""
+    };
+    let pwa_head = if opts.pwa {
+        format!(
+            "\n    <link rel=\"manifest\" href=\"{}/manifest.json\">\n    <meta name=\"theme-color\" content=\"#ffffff\">\n    <meta name=\"apple-mobile-web-app-capable\" content=\"yes\">",
+            opts.base
+        )
+    } else {
+        String::new()
+    };
+    let custom_css = if opts.custom_css {
+        format!("\n    <link rel=\"stylesheet\" href=\"{}/custom.css\">", opts.base)
+    } else {
+        String::new()
+    };
+    let pinned = if opts.pinned.is_empty() {
+        String::new()
+    } else {
+        let items: String = opts
+            .pinned
+            .iter()
+            .map(|n| format!("\n            <li><a href=\"{}/{n}\">{n}</a></li>", opts.base, n = html_escape(n)))
+            .collect();
+        format!("\n        <h3>Pinned</h3>\n        <ul id=\"pinned-list\">{items}\n        </ul>")
+    };
+    // 公告带上内容哈希，关闭后只对同一条公告生效，换了公告会重新显示
+    let notice = match opts.notice {
+        Some(html) => format!(
+            "\n    <div id=\"site-notice\" class=\"site-notice\" data-key={key}>{html}<span class=\"close-btn\" id=\"site-notice-close\">&times;</span></div>",
+            key = content_etag(html.as_bytes()),
+        ),
+        None => String::new(),
+    };
+    let unlock_link = if opts.locked {
+        r##"<a href="#" id="unlockEdit">&nbsp; | &nbsp;🔒 read-only</a>"##
+    } else {
+        ""
+    };
+    let load_full_link = if opts.truncated {
+        r##"<a href="#" id="loadFull">&nbsp; | &nbsp;⤓ load full content</a>"##
+    } else {
+        ""
+    };
+    // 传给前端脚本的配置
+    let config = serde_json::json!({
+        "uploads": opts.uploads_enabled,
+        "immutable": opts.immutable,
+        "locked": opts.locked,
+        "truncated": opts.truncated,
+        "render": opts.render,
+        "insert_format": opts.insert_format.as_str(),
+        "base": opts.base,
+    });
+
     // 前半部分用 format! 插入变量
     let mut html = format!(
         r##"<!DOCTYPE html>
@@ -378,18 +3816,20 @@ fn render_html(note: &str, content_escaped: &str, excerpt: &str) -> String {
     <meta charset="utf-8">
     <meta name="viewport" content="width=device-width, initial-scale=1.0">
     <title>web-mini-note · {note}</title>
-    <link rel="shortcut icon" href="/favicon.ico">
-    <link rel="stylesheet" href="/styles.css">
+    <link rel="shortcut icon" href="{base}/favicon.ico">
+    <link rel="stylesheet" href="{base}/styles.css">{custom_css}
     <meta name="description" content="📔 {desc}">
-    <script src="/js/qrcode.min.js"></script> 
-    <script src="/js/clipboard.min.js"></script>
-    <script src="/js/marked.min.js"></script>
-    <script src="/js/mousetrap.min.js"></script>
+    <meta property="og:title" content="web-mini-note · {note}">
+    <meta property="og:description" content="{desc}">{robots}{pwa_head}
+    <script src="{base}/js/qrcode.min.js"></script> 
+    <script src="{base}/js/clipboard.min.js"></script>
+    <script src="{base}/js/marked.min.js"></script>
+    <script src="{base}/js/mousetrap.min.js"></script>
 </head>
-<body>
+<body>{notice}
     <div id="sidebar" class="sidebar">
-        <script src="/history.js"></script>
-        <span class="close-btn" onclick="toggleSidebar()">&times;</span>
+        <script src="{base}/history.js"></script>
+        <span class="close-btn" onclick="toggleSidebar()">&times;</span>{pinned}
         <h3>Recent Notes</h3>
         <ul id="history-list"></ul>
     </div>
@@ -397,29 +3837,43 @@ fn render_html(note: &str, content_escaped: &str, excerpt: &str) -> String {
         <div id="qrcodePopup">
             <div id="qrcode"></div>
         </div>
-        <textarea class="mousetrap" id="content" spellcheck="false" autocapitalize="off" autocomplete="off" autocorrect="off">{content}</textarea>
+        <textarea class="mousetrap" id="content" spellcheck="false" autocapitalize="off" autocomplete="off" autocorrect="off"{readonly}>{content}</textarea>
         <button id="clippy" class="btn">
-            <img src="/clippy.svg" alt="Copy to clipboard" style="width: 12px; height: 16px;">
+            <img src="{base}/clippy.svg" alt="Copy to clipboard" style="width: 12px; height: 16px;">
         </button>
         <div id="markdown-content" style="display: none"></div>
         <div class="link">
-            <a href="/">💡 new &nbsp;|&nbsp;</a>
+            <a href="{base}/">💡 new &nbsp;|&nbsp;</a>
             <a href="#" id="renderMarkdown">note/{note}&nbsp;<label id="renderStatus" style="cursor: pointer">🔓</label></a>
             <a href="#" id="showQRCode" class="copyBtn">&nbsp; | &nbsp;🔗 share</a>
             <a href="#" id="showHistory" class="showHistory">&nbsp; | &nbsp;📜 history</a>
-            <a href="#" id="uploadTrigger">&nbsp; | &nbsp;⤴ upload</a>
+            {upload_link}
+            {load_full_link}
+            {unlock_link}
         </div>
     </div>
     <pre id="printable"></pre>
     <div id="qrcode"></div>
-    <script src="/markdown.js"></script>
-    <script src="/copy.js"></script>
-    <script src="/script.js"></script>
-    <input type="file" id="fileInput" style="display:none" />
+    <script>var NOTE_CONFIG = {config};</script>
+    <script src="{base}/markdown.js"></script>
+    <script src="{base}/copy.js"></script>
+    <script src="{base}/script.js"></script>
+    <input type="file" id="fileInput" multiple style="display:none" />
 "##,
         note = note,
         content = content_escaped,
-        desc = html_attr_escape(&format!("{}", excerpt)),
+        desc = html_attr_escape(excerpt),
+        upload_link = upload_link,
+        pwa_head = pwa_head,
+        custom_css = custom_css,
+        pinned = pinned,
+        notice = notice,
+        load_full_link = load_full_link,
+        unlock_link = unlock_link,
+        robots = if opts.noindex { "\n    <meta name=\"robots\" content=\"noindex\">" } else { "" },
+        base = opts.base,
+        readonly = if opts.immutable || opts.truncated || opts.locked { " readonly" } else { "" },
+        config = script_json(&config),
     );
 
     // 纯 JS 片段用原始字符串拼接，避免 format! 解析花括号
@@ -433,28 +3887,38 @@ fn render_html(note: &str, content_escaped: &str, excerpt: &str) -> String {
         el.addEventListener('click', function(e){ e.preventDefault(); input.click(); });
         input.addEventListener('change', async function(){
           if(!input.files || input.files.length === 0) return;
-          var f = input.files[0];
-          if(f.size > 100*1024*1024){ showNotification('file too large (>100MB)'); return; }
           var fd = new FormData();
-          fd.append('file', f);
+          for(var i = 0; i < input.files.length; i++){
+            var f = input.files[i];
+            if(f.size > 100*1024*1024){ showNotification('file too large (>100MB): ' + f.name); return; }
+            fd.append('file', f);
+          }
           try{
             showNotification('uploading...');
-            var resp = await fetch('/upload', { method: 'POST', body: fd });
-            if(!resp.ok){ showNotification('upload failed'); return; }
+            var resp = await fetch(NOTE_CONFIG.base + '/upload', { method: 'POST', body: fd });
+            if(!resp.ok){
+              var err = null;
+              try{ err = await resp.json(); }catch(_){}
+              showNotification(uploadErrorMessage(err));
+              return;
+            }
             var data = await resp.json();
+            // 多个文件时返回 207 及逐个结果：插入成功的，提示失败的
+            var items = resp.status === 207 ? data.results : [data];
+            var links = [];
+            var failed = [];
+            items.forEach(function(item){
+              if(item.ok === false){ failed.push(item.file + ': ' + uploadErrorMessage(item)); return; }
+              links.push(uploadSnippet(item.url, item.display_name || item.name || '', item.is_image));
+            });
             var cursorPos = ta.selectionStart || 0;
             var before = ta.value.substring(0, cursorPos);
             var after = ta.value.substring(cursorPos);
-            var insert = '';
-            if(data.is_image){
-              insert = '![](' + data.url + ')';
-            } else {
-              insert = '[' + (data.name || 'attachment') + '](' + data.url + ')';
-            }
+            var insert = links.join('\n');
             ta.value = before + insert + after;
             ta.selectionStart = ta.selectionEnd = cursorPos + insert.length;
             ta.focus();
-            showNotification('uploaded');
+            showNotification(failed.length ? 'not uploaded: ' + failed.join('; ') : 'uploaded');
           }catch(e){
             showNotification('upload error');
           }finally{
@@ -464,16 +3928,883 @@ fn render_html(note: &str, content_escaped: &str, excerpt: &str) -> String {
       }
     })();
     </script>
-    </body>
-    </html>
-    "##;
+"##;
+
+    const PWA_JS: &str = r##"
+    <script>
+    if ('serviceWorker' in navigator) {
+      navigator.serviceWorker.register(NOTE_CONFIG.base + '/sw.js').catch(function(){});
+    }
+    </script>
+"##;
+
+    // 输入编辑令牌，服务端校验后写入 Cookie 并刷新页面
+    const UNLOCK_JS: &str = r##"
+    <script>
+    (function(){
+      var el = document.getElementById('unlockEdit');
+      el.addEventListener('click', function(e){
+        e.preventDefault();
+        var token = window.prompt('edit token');
+        if (!token) return;
+        fetch(NOTE_CONFIG.base + '/api/edit-token', {
+          method: 'POST',
+          headers: { 'Content-Type': 'application/x-www-form-urlencoded; charset=UTF-8' },
+          body: 'token=' + encodeURIComponent(token)
+        }).then(function(r){
+          if (r.ok) window.location.reload();
+          else showNotification('invalid edit token');
+        }).catch(function(){ showNotification('unlock failed'); });
+      });
+    })();
+    </script>
+"##;
+
+    // 公告关闭状态记在 localStorage 中
+    const NOTICE_JS: &str = r##"
+    <script>
+    (function(){
+      var el = document.getElementById('site-notice');
+      var key = el.getAttribute('data-key');
+      try { if (localStorage.getItem('siteNoticeDismissed') === key) el.style.display = 'none'; } catch (e) {}
+      document.getElementById('site-notice-close').addEventListener('click', function(){
+        el.style.display = 'none';
+        try { localStorage.setItem('siteNoticeDismissed', key); } catch (e) {}
+      });
+    })();
+    </script>
+"##;
 
-    html.push_str(UPLOAD_JS);
+    // 加载完整内容：按原样取回 raw 内容后替换编辑框，并恢复可编辑
+    const LOAD_FULL_JS: &str = r##"
+    <script>
+    (function(){
+      var el = document.getElementById('loadFull');
+      if (!el) return;
+      el.addEventListener('click', function(e){
+        e.preventDefault();
+        showNotification('loading...');
+        fetch(window.location.pathname + '?raw=verbatim', { cache: 'no-store' })
+          .then(function(r){
+            if (!r.ok) throw new Error('load failed');
+            return r.text();
+          })
+          .then(function(text){
+            textarea.value = text;
+            content = text;
+            NOTE_CONFIG.truncated = false;
+            if (!NOTE_CONFIG.immutable) textarea.removeAttribute('readonly');
+            el.style.display = 'none';
+            showNotification('full content loaded');
+          })
+          .catch(function(){ showNotification('load failed'); });
+      });
+    })();
+    </script>
+"##;
+
+    if opts.uploads_enabled {
+        html.push_str(UPLOAD_JS);
+    }
+    if opts.truncated {
+        html.push_str(LOAD_FULL_JS);
+    }
+    if opts.pwa {
+        html.push_str(PWA_JS);
+    }
+    if opts.notice.is_some() {
+        html.push_str(NOTICE_JS);
+    }
+    if opts.locked {
+        html.push_str(UNLOCK_JS);
+    }
+    html.push_str("    </body>\n    </html>\n");
     html
 }
 
+// 内嵌到 <script> 中的 JSON，转义 "</" 防止提前闭合标签
+fn script_json(value: &serde_json::Value) -> String {
+    value.to_string().replace("</", "<\\/")
+}
+
+// 首页介绍页（ROOT_BEHAVIOR=landing）
+fn render_landing_html(note: &str, base: &str) -> String {
+    format!(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>web-mini-note</title>
+    <link rel="shortcut icon" href="{base}/favicon.ico">
+    <link rel="stylesheet" href="{base}/styles.css">
+    <meta name="description" content="📔 a minimalist online notepad">
+</head>
+<body>
+    <div class="container">
+        <div id="markdown-content">
+            <h1>📔 web-mini-note</h1>
+            <p>A minimalist online notepad. Every note lives at its own URL and is saved automatically while you type.</p>
+            <ul>
+                <li>Markdown preview with <code>Ctrl+E</code></li>
+                <li>Paste or upload images and files</li>
+                <li>Share a note by sharing its link; <code>curl</code> it to get plain text</li>
+            </ul>
+            <p><a href="{base}/{note}">💡 Start a new note →</a></p>
+        </div>
+    </div>
+</body>
+</html>
+"##,
+        note = note,
+        base = base,
+    )
+}
+
+// 只读页面：复用 styles.css 中 #markdown-content 的排版，不加载编辑器相关脚本
+fn render_read_html(note: &str, rendered: &str, excerpt: &str, base: &str) -> String {
+    format!(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>web-mini-note · {note}</title>
+    <link rel="shortcut icon" href="{base}/favicon.ico">
+    <link rel="stylesheet" href="{base}/styles.css">
+    <meta name="description" content="📔 {desc}">
+    <meta property="og:title" content="web-mini-note · {note}">
+    <meta property="og:description" content="{desc}">
+</head>
+<body>
+    <div class="container">
+        <div id="markdown-content">{rendered}</div>
+        <div class="link">
+            <a href="{base}/{note}">✎ edit&nbsp;|&nbsp;</a>
+            <a href="{base}/{note}?raw">raw</a>
+        </div>
+    </div>
+</body>
+</html>
+"##,
+        note = note,
+        rendered = rendered,
+        desc = html_attr_escape(excerpt),
+        base = base,
+    )
+}
+
 fn html_attr_escape(input: &str) -> String {
     // 对于 meta content
-    html_escape(&input.replace('"', "\""))
+    html_escape(input)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::FromRequest;
+
+    // 每个测试使用独立的临时 SAVE_PATH，其余配置取默认值
+    fn test_state(name: &str) -> AppState {
+        let dir = env::temp_dir().join(format!("wmn-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        AppState {
+            chunked: Arc::new(chunked::ChunkedUploads::new(
+                &dir,
+                std::time::Duration::from_secs(CHUNKED_UPLOAD_TTL),
+                MAX_PENDING_UPLOADS,
+            )),
+            save_path: Arc::new(dir),
+            file_limit: 100000,
+            single_file_size_limit: 10240,
+            truncate_oversize: false,
+            static_root: Arc::new(PathBuf::from(".")),
+            static_extensions: Arc::new(Vec::new()),
+            strict_note_ids: false,
+            reject_short_ids: false,
+            strict_query: false,
+            listing_enabled: true,
+            max_list_results: 100,
+            excerpt_length: 150,
+            snippet_length: 80,
+            stats: Arc::new(FileStats::default()),
+            access_times: None,
+            root_behavior: RootBehavior::Random,
+            note_id_length: 5,
+            random_alphabet: Arc::new(DEFAULT_RANDOM_ALPHABET.bytes().collect()),
+            note_id_retries: 10,
+            note_id_retry_warn: 3,
+            metrics: Arc::new(Metrics::new(metrics::DEFAULT_SIZE_BUCKETS)),
+            uploads_enabled: true,
+            embed_origins: Arc::new("*".to_string()),
+            sanitize: Arc::new(SanitizeOptions::default()),
+            admin_token: None,
+            edit_token: None,
+            note_size_hard_cap: 2 * 1024 * 1024,
+            time_format: None,
+            max_decompressed_body: 8 * 1024 * 1024,
+            compress_notes: false,
+            insert_format: InsertFormat::Markdown,
+            verify_content: false,
+            auto_render: false,
+            audit_log: None,
+            backup: None,
+            empty_post_clears: false,
+            upload_keys: Arc::new(idempotency::IdempotencyCache::new(
+                std::time::Duration::from_secs(IDEMPOTENCY_TTL),
+                MAX_IDEMPOTENCY_KEYS,
+            )),
+            base_path: Arc::new(String::new()),
+            public_url: None,
+            trust_proxy_headers: false,
+            pwa_manifest: None,
+            pinned_notes: Arc::new(Vec::new()),
+            note_max_idle_days: 0,
+            editor_display_limit: 0,
+            templates_dir: None,
+            custom_css: None,
+            site_notice: None,
+            read_limiter: None,
+            upload_slots: None,
+            file_slots: None,
+            raw_newline: RawNewline::Verbatim,
+            max_tmp_files: 0,
+            legacy_cache_headers: true,
+            upload_name_length: 40,
+        }
+    }
+
+    fn remote() -> ConnectInfo<SocketAddr> {
+        ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000)))
+    }
+
+    async fn body_json(resp: Response) -> serde_json::Value {
+        let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    fn multipart_body(parts: &[(&str, &[u8])]) -> (String, Vec<u8>) {
+        let boundary = "wmn-test-boundary";
+        let mut body = Vec::new();
+        for (name, data) in parts {
+            body.extend_from_slice(
+                format!(
+                    "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\
+                     Content-Type: application/octet-stream\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+        (format!("multipart/form-data; boundary={boundary}"), body)
+    }
+
+    async fn upload(state: &AppState, content_type: &str, body: Vec<u8>) -> Response {
+        upload_with(state, remote(), HeaderMap::new(), content_type, body).await
+    }
+
+    async fn upload_with(
+        state: &AppState,
+        from: ConnectInfo<SocketAddr>,
+        headers: HeaderMap,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> Response {
+        // 经由小缓冲的管道发送请求体：与真实连接一样分批到达，读到超限的字段时才报错
+        let (reader, mut writer) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            let _ = writer.write_all(&body).await;
+        });
+        let req = axum::http::Request::builder()
+            .method("POST")
+            .uri("/upload")
+            .header("content-type", content_type)
+            .body(Body::from_stream(ReaderStream::new(reader)))
+            .unwrap();
+        let multipart = Multipart::from_request(req, &()).await.unwrap();
+        upload_file(State(state.clone()), from, headers, multipart).await.into_response()
+    }
+
+    async fn post(state: &AppState, note: &str, headers: &[(&str, &str)], text: &str) -> Response {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(axum::http::HeaderName::from_bytes(name.as_bytes()).unwrap(), value.parse().unwrap());
+        }
+        if !map.contains_key("content-type") {
+            map.insert("content-type", "application/x-www-form-urlencoded; charset=UTF-8".parse().unwrap());
+        }
+        let body = serde_urlencoded::to_string([("text", text)]).unwrap();
+        post_note(State(state.clone()), Path(note.to_string()), remote(), map, Bytes::from(body)).await
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decode_body_limits_decompressed_size() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-encoding", "gzip".parse().unwrap());
+        // 1MB 的重复内容压缩后只有 1KB 左右，解压后超出上限
+        let bomb = gzip(&vec![b'a'; 1024 * 1024]);
+        assert!(bomb.len() < 4096);
+        let err = decode_body(&headers, &bomb, 64 * 1024).unwrap_err();
+        assert_eq!(err.0, StatusCode::PAYLOAD_TOO_LARGE);
+        // 恰好等于上限时允许
+        let body = gzip(&[b'a'; 1024]);
+        assert_eq!(decode_body(&headers, &body, 1024).unwrap().len(), 1024);
+        assert_eq!(decode_body(&headers, b"not gzip", 1024).unwrap_err().0, StatusCode::BAD_REQUEST);
+        headers.insert("content-encoding", "br".parse().unwrap());
+        assert_eq!(decode_body(&headers, &body, 1024).unwrap_err().0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn gzip_post_over_limit_is_rejected() {
+        let state = AppState { max_decompressed_body: 64 * 1024, ..test_state("gzip-post") };
+        let form = serde_urlencoded::to_string([("text", "a".repeat(1024 * 1024))]).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("content-encoding", "gzip".parse().unwrap());
+        headers.insert("content-type", "application/x-www-form-urlencoded".parse().unwrap());
+        let body = Bytes::from(gzip(form.as_bytes()));
+        let resp = post_note(State(state.clone()), Path("gz1".to_string()), remote(), headers, body).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(!state.save_path.join("gz1").exists());
+    }
+
+    #[test]
+    fn public_base_ignores_forwarded_headers_unless_trusted() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "notes.local:8080".parse().unwrap());
+        headers.insert("x-forwarded-host", "evil.example".parse().unwrap());
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        let state = test_state("public-base");
+        assert_eq!(public_base(&state, &headers), "http://notes.local:8080");
+        let state = AppState { trust_proxy_headers: true, ..state };
+        assert_eq!(public_base(&state, &headers), "https://evil.example");
+        // PUBLIC_URL 总是优先
+        let state = AppState { public_url: Some(Arc::new("https://example.com/n".to_string())), ..state };
+        assert_eq!(public_base(&state, &headers), "https://example.com/n");
+    }
+
+    #[test]
+    fn etag_matches_lists_and_wildcard() {
+        let etag = content_etag(b"hello");
+        assert!(etag_matches(&etag, Some(&etag)));
+        assert!(etag_matches(&format!("\"other\", {etag}"), Some(&etag)));
+        assert!(etag_matches("*", Some(&etag)));
+        assert!(!etag_matches("\"other\"", Some(&etag)));
+        // note 不存在时连 * 也不匹配
+        assert!(!etag_matches("*", None));
+    }
+
+    #[test]
+    fn content_type_value_falls_back_for_invalid_values() {
+        let path = FsPath::new("odd.bin");
+        assert_eq!(content_type_value("image/png", path), "image/png");
+        for crafted in ["text/html\r\nx-injected: 1", "text/plain\0", "\x7f"] {
+            assert_eq!(content_type_value(crafted, path), "application/octet-stream");
+        }
+    }
+
+    #[tokio::test]
+    async fn upload_with_crafted_type_is_served_as_octet_stream() {
+        let state = test_state("crafted-type");
+        fs::write(state.save_path.join("1_odd.png"), b"data").unwrap();
+        meta::save_upload_type(&state.save_path, "1_odd.png", "image/png\r\nset-cookie: a=b").unwrap();
+        let resp = serve_tmp_file(State(state.clone()), Path("1_odd.png".to_string()), remote()).await.into_response();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "application/octet-stream");
+        assert!(resp.headers().get("set-cookie").is_none());
+    }
+
+    #[tokio::test]
+    async fn tmp_route_only_serves_upload_names() {
+        let state = test_state("tmp-names");
+        for name in ["abcde", "1700000000_file", "1700000000_a.txt"] {
+            fs::write(state.save_path.join(name), "data").unwrap();
+        }
+        let resp = serve_tmp_file(State(state.clone()), Path("abcde".to_string()), remote()).await.into_response();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        // 也可能是 note 的名字不缓存；普通上传文件交给 UPLOAD_CACHE_MAX_AGE
+        let resp = serve_tmp_file(State(state.clone()), Path("1700000000_file".to_string()), remote()).await;
+        let resp = resp.into_response();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers()["cache-control"].to_str().unwrap().contains("no-cache"));
+        let resp = serve_tmp_file(State(state.clone()), Path("1700000000_a.txt".to_string()), remote()).await;
+        let resp = resp.into_response();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get("cache-control").is_none());
+    }
+
+    #[test]
+    fn raw_mime_falls_back_to_text_plain() {
+        assert_eq!(raw_content_type(Some("text/markdown"), None), "text/markdown; charset=utf-8");
+        for crafted in ["text/html", "application/json\r\nx: y", "", "TEXT/PLAIN;x"] {
+            assert_eq!(raw_content_type(Some(crafted), Some("json")), "text/plain; charset=utf-8");
+        }
+        assert_eq!(raw_content_type(None, Some("json")), "application/json; charset=utf-8");
+    }
+
+    #[tokio::test]
+    async fn raw_note_with_invalid_mime_is_text_plain() {
+        let state = test_state("raw-mime");
+        fs::write(state.save_path.join("mime1"), "<script>x</script>").unwrap();
+        let query = NoteQuery { raw: Some(String::new()), mime: Some("text/html".to_string()), ..NoteQuery::default() };
+        let resp = get(&state, "mime1", query, "curl/8").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "text/plain; charset=utf-8");
+    }
+
+    #[test]
+    fn accepts_encoding_honours_q_and_wildcard() {
+        assert!(accepts_encoding("gzip, deflate, br", "br"));
+        assert!(accepts_encoding("GZIP;q=0.5", "gzip"));
+        assert!(!accepts_encoding("gzip;q=0, br", "gzip"));
+        assert!(!accepts_encoding("gzip; q=0.0", "gzip"));
+        // * 覆盖没有单独列出的编码，单独列出的以自身为准
+        assert!(accepts_encoding("*", "br"));
+        assert!(accepts_encoding("gzip;q=0, *", "br"));
+        assert!(!accepts_encoding("gzip;q=0, *", "gzip"));
+        assert!(!accepts_encoding("*;q=0", "gzip"));
+        assert!(!accepts_encoding("identity", "gzip"));
+        // 没有 Accept-Encoding 头
+        assert!(!accepts_encoding("", "gzip"));
+    }
+
+    #[test]
+    fn sanitize_filename_replaces_and_falls_back() {
+        let opts = SanitizeOptions::default();
+        assert_eq!(sanitize_filename("a/b\\c.txt", &opts), "a_b_c.txt");
+        // 连续的非法字符合并为一个 "_"
+        assert_eq!(sanitize_filename("a<>|b", &opts), "a_b");
+        // 全是非法字符时使用回退名
+        assert_eq!(sanitize_filename("\\/:*?", &opts), "file");
+        assert_eq!(sanitize_filename("", &opts), "file");
+        let opts = SanitizeOptions { fallback: "upload".to_string(), ..SanitizeOptions::default() };
+        assert_eq!(sanitize_filename("<>.", &opts), "upload");
+        // Unicode 名字原样保留，控制字符总是替换
+        let opts = SanitizeOptions::default();
+        assert_eq!(sanitize_filename("报告 ✓ été.pdf", &opts), "报告 ✓ été.pdf");
+        assert_eq!(sanitize_filename("a\nb.txt", &opts), "a_b.txt");
+    }
+
+    #[test]
+    fn sanitize_filename_percent_mode() {
+        let opts = SanitizeOptions { percent_encode: true, ..SanitizeOptions::default() };
+        assert_eq!(sanitize_filename("a/b?.txt", &opts), "a%2Fb%3F.txt");
+        // "%" 本身也编码，保证结果可以无歧义地还原
+        assert_eq!(sanitize_filename("100%.txt", &opts), "100%25.txt");
+        assert_eq!(sanitize_filename("报告.pdf", &opts), "报告.pdf");
+        assert_eq!(sanitize_filename("a\tb", &opts), "a%09b");
+    }
+
+    #[test]
+    fn static_asset_prefers_precompressed_variant() {
+        let state = test_state("static-variant");
+        let path = state.save_path.join("app.css");
+        fs::write(&path, "body{}").unwrap();
+        fs::write(state.save_path.join("app.css.gz"), "gz").unwrap();
+        fs::write(state.save_path.join("app.css.br"), "br").unwrap();
+        let serve = |accept: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(accept) = accept {
+                headers.insert("accept-encoding", accept.parse().unwrap());
+            }
+            let resp = static_asset_response(&path, &headers);
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()["content-type"], "text/css");
+            assert_eq!(resp.headers()["vary"], "Accept-Encoding");
+            resp.headers().get("content-encoding").map(|v| v.to_str().unwrap().to_string())
+        };
+        assert_eq!(serve(Some("gzip, br")).as_deref(), Some("br"));
+        assert_eq!(serve(Some("gzip, br;q=0")).as_deref(), Some("gzip"));
+        assert_eq!(serve(Some("*")).as_deref(), Some("br"));
+        assert_eq!(serve(Some("identity")), None);
+        assert_eq!(serve(None), None);
+        // 没有对应的压缩文件时退回原文件
+        fs::remove_file(state.save_path.join("app.css.br")).unwrap();
+        assert_eq!(serve(Some("br")), None);
+    }
+
+    async fn get(state: &AppState, note: &str, query: NoteQuery, ua: &str) -> Response {
+        let mut headers = HeaderMap::new();
+        headers.insert("user-agent", ua.parse().unwrap());
+        let uri: Uri = format!("/{note}").parse().unwrap();
+        get_note(State(state.clone()), Path(note.to_string()), Query(query), remote(), headers, uri).await
+    }
+
+    #[tokio::test]
+    async fn note_deleted_before_read_is_not_found() {
+        let state = test_state("deleted-mid-read");
+        let path = state.save_path.join("gone1");
+        fs::write(&path, "soon deleted").unwrap();
+        // 模拟存在性检查之后、读取之前被并发删除：读取直接按打开结果处理
+        fs::remove_file(&path).unwrap();
+        let err = raw_note_body(&path, RawNewline::Verbatim).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        // 打开的不是普通文件时同样视为不存在
+        let err = raw_note_body(&state.save_path, RawNewline::Verbatim).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        let raw = NoteQuery { raw: Some(String::new()), ..NoteQuery::default() };
+        assert_eq!(get(&state, "gone1", raw, "curl/8").await.status(), StatusCode::NOT_FOUND);
+        let read = NoteQuery { view: Some("read".to_string()), ..NoteQuery::default() };
+        assert_eq!(get(&state, "gone1", read, "Mozilla/5.0").await.status(), StatusCode::NOT_FOUND);
+        // 编辑页面对不存在的 note 显示空白编辑框
+        assert_eq!(get(&state, "gone1", NoteQuery::default(), "Mozilla/5.0").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn note_path_occupied_by_directory_is_conflict() {
+        let state = test_state("dir-collision");
+        let dir = state.save_path.join("dir1");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("inner"), "keep").unwrap();
+        for query in [
+            NoteQuery { raw: Some(String::new()), ..NoteQuery::default() },
+            NoteQuery { view: Some("read".to_string()), ..NoteQuery::default() },
+            NoteQuery::default(),
+        ] {
+            assert_eq!(get(&state, "dir1", query, "Mozilla/5.0").await.status(), StatusCode::CONFLICT);
+        }
+        assert_eq!(post(&state, "dir1", &[], "text").await.status(), StatusCode::CONFLICT);
+        // 空内容（删除）也不能动这个目录
+        assert_eq!(post(&state, "dir1", &[], "").await.status(), StatusCode::CONFLICT);
+        assert_eq!(fs::read_to_string(dir.join("inner")).unwrap(), "keep");
+    }
+
+    #[test]
+    fn floor_char_boundary_never_splits_a_character() {
+        // "笔" 与 "记" 各占 3 字节
+        let text = "ab笔记";
+        assert_eq!(floor_char_boundary(text, 2), 2);
+        assert_eq!(floor_char_boundary(text, 3), 2);
+        assert_eq!(floor_char_boundary(text, 4), 2);
+        assert_eq!(floor_char_boundary(text, 5), 5);
+        assert_eq!(floor_char_boundary(text, 100), text.len());
+        assert_eq!(floor_char_boundary("😀", 3), 0);
+    }
+
+    #[tokio::test]
+    async fn oversize_note_is_truncated_on_a_char_boundary() {
+        let state = AppState { single_file_size_limit: 10, truncate_oversize: true, ..test_state("truncate") };
+        // 上限 10 字节落在第 4 个汉字（第 10-12 字节）中间
+        let resp = post(&state, "trunc1", &[], "笔记笔记笔记").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().contains_key("x-note-truncated"));
+        assert_eq!(fs::read_to_string(state.save_path.join("trunc1")).unwrap(), "笔记笔");
+    }
+
+    #[tokio::test]
+    async fn non_form_post_leaves_note_untouched() {
+        let state = test_state("post-content-type");
+        let path = state.save_path.join("form1");
+        fs::write(&path, "keep me").unwrap();
+        for (content_type, body) in [
+            ("application/json", r#"{"text":"x"}"#),
+            ("text/plain", "hello"),
+            ("multipart/form-data; boundary=x", "text=x"),
+        ] {
+            let mut headers = HeaderMap::new();
+            headers.insert("content-type", content_type.parse().unwrap());
+            let resp = post_note(State(state.clone()), Path("form1".into()), remote(), headers, body.into()).await;
+            assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE, "{content_type}");
+        }
+        // 缺少 Content-Type 同样拒绝
+        let headers = HeaderMap::new();
+        let resp = post_note(State(state.clone()), Path("form1".into()), remote(), headers, Bytes::new()).await;
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep me");
+        // 带参数、大小写不同的表单类型照常接受
+        let form_type = ("content-type", "Application/X-WWW-Form-Urlencoded;charset=utf-8");
+        let resp = post(&state, "form1", &[form_type], "new").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    }
+
+    #[tokio::test]
+    async fn read_limit_covers_every_content_route() {
+        let limiter = ratelimit::RateLimiter::new(1, std::time::Duration::from_secs(60));
+        let state = AppState { read_limiter: Some(Arc::new(limiter)), ..test_state("read-limit") };
+        fs::write(state.save_path.join("rl1"), "secret").unwrap();
+        // 唯一的一次读取额度用在 /:note 上，其余返回内容的路由都应被限流
+        let raw = NoteQuery { raw: Some(String::new()), ..NoteQuery::default() };
+        assert_eq!(get(&state, "rl1", raw, "curl/8").await.status(), StatusCode::OK);
+        let resp = embed_note(State(state.clone()), Path("rl1".to_string()), remote()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        fs::write(state.save_path.join("1700000000_rl"), "secret").unwrap();
+        let resp = serve_tmp_file(State(state.clone()), Path("1700000000_rl".to_string()), remote()).await;
+        assert_eq!(resp.into_response().status(), StatusCode::TOO_MANY_REQUESTS);
+        let resp = note_fragment(State(state.clone()), Path("rl1".to_string()), remote()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let resp = note_excerpt(State(state.clone()), Path("rl1".to_string()), remote(), HeaderMap::new()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let query = ListQuery { limit: None, q: None, sort: None };
+        let resp = list_notes(State(state.clone()), Query(query), remote()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn fork_does_not_inherit_per_note_flags() {
+        let state = test_state("fork-meta");
+        fs::write(state.save_path.join("tmpl1"), "template").unwrap();
+        let source = meta::NoteMeta {
+            lang: Some("json".to_string()),
+            description: Some("a template".to_string()),
+            immutable: true,
+            noindex: true,
+            size_limit: Some(1024 * 1024),
+            ..meta::NoteMeta::default()
+        };
+        meta::save(&state.save_path, "tmpl1", &source).unwrap();
+        let resp = fork_note(State(state.clone()), Path("tmpl1".to_string())).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let fork = body_json(resp).await["note"].as_str().unwrap().to_string();
+        let fork_meta = meta::load(&state.save_path, &fork);
+        assert_eq!(fork_meta.lang.as_deref(), Some("json"));
+        assert_eq!(fork_meta.description.as_deref(), Some("a template"));
+        assert!(!fork_meta.immutable && !fork_meta.noindex);
+        assert_eq!(fork_meta.size_limit, None);
+        // 副本可以正常编辑
+        assert_eq!(post(&state, &fork, &[], "edited").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn chunked_upload_is_renamed_into_place() {
+        let state = test_state("chunked");
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x02\0\0\0\x03\x08\x06\0\0\0".to_vec();
+        png.resize(64, 0);
+        let id = state.chunked.init("pic".to_string(), png.len()).unwrap();
+        assert_eq!(state.chunked.append(&id, 0, &png[..40]).unwrap(), 40);
+        // 偏移不符的分块被拒绝，不会覆盖已写入的数据
+        assert!(matches!(state.chunked.append(&id, 0, &png[40..]), Err(chunked::ChunkError::OffsetMismatch(40))));
+        assert_eq!(state.chunked.append(&id, 40, &png[40..]).unwrap(), 64);
+
+        let resp = upload_complete(State(state.clone()), Path(id.clone()), remote()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let json = body_json(resp).await;
+        assert_eq!(json["is_image"], true);
+        assert_eq!((json["width"].as_u64(), json["height"].as_u64()), (Some(2), Some(3)));
+        let stored = json["name"].as_str().unwrap();
+        assert_eq!(fs::read(state.save_path.join(stored)).unwrap(), png);
+        assert_eq!(meta::load_upload_type(&state.save_path, stored).as_deref(), Some("image/png"));
+        assert!(!state.save_path.join(meta::META_DIR).join("_partial").join(&id).exists());
+        assert!(state.chunked.status(&id).is_none());
+    }
+
+    #[test]
+    fn reaper_keeps_pinned_alias_targets_and_audits_deletes() {
+        let mut state = test_state("reap");
+        let log_path = env::temp_dir().join(format!("wmn-audit-reap-{}.log", std::process::id()));
+        let _ = fs::remove_file(&log_path);
+        state.audit_log = Some(audit::AuditLog::open(&log_path).unwrap());
+        state.note_max_idle_days = 1;
+        state.pinned_notes = Arc::new(vec!["pin".to_string()]);
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(10 * 86400);
+        for note in ["kept", "gone"] {
+            fs::write(state.save_path.join(note), "text").unwrap();
+            fs::File::options().write(true).open(state.save_path.join(note)).unwrap().set_modified(old).unwrap();
+        }
+        let alias = meta::NoteMeta { alias_of: Some("kept".to_string()), ..Default::default() };
+        meta::save(&state.save_path, "pin", &alias).unwrap();
+        let gone_meta = meta::NoteMeta { lang: Some("rust".to_string()), ..Default::default() };
+        meta::save(&state.save_path, "gone", &gone_meta).unwrap();
+
+        reap_idle_notes(&state).unwrap();
+        assert!(state.save_path.join("kept").is_file());
+        assert!(!state.save_path.join("gone").exists());
+        assert!(!state.save_path.join(meta::META_DIR).join("gone.json").exists());
+        let log = fs::read_to_string(&log_path).unwrap();
+        let event: serde_json::Value = serde_json::from_str(log.lines().next().unwrap()).unwrap();
+        assert_eq!((event["event"].as_str(), event["note"].as_str()), (Some("reap"), Some("gone")));
+        assert!(event["ip"].is_null());
+        assert_eq!(log.lines().count(), 1);
+        let _ = fs::remove_file(&log_path);
+    }
+
+    #[tokio::test]
+    async fn chunked_upload_requests_take_upload_slots() {
+        let slots = Arc::new(ratelimit::ConcurrencyLimiter::new(1));
+        let state = AppState { upload_slots: Some(slots.clone()), ..test_state("chunked-slots") };
+        let id = state.chunked.init("a.txt".to_string(), 2).unwrap();
+        // 同一 IP 已有一个上传在进行，init、分块与 complete 都返回 429
+        let held = slots.acquire(remote().0.ip()).unwrap();
+        let form = UploadInitForm { name: Some("b.txt".to_string()), size: 2 };
+        let resp = upload_init(State(state.clone()), remote(), Form(form)).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let mut headers = HeaderMap::new();
+        headers.insert("upload-offset", HeaderValue::from_static("0"));
+        let chunk = Bytes::from("hi");
+        let resp = upload_chunk(State(state.clone()), Path(id.clone()), remote(), headers.clone(), chunk.clone()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let resp = upload_complete(State(state.clone()), Path(id.clone()), remote()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        drop(held);
+        let resp = upload_chunk(State(state.clone()), Path(id.clone()), remote(), headers, chunk).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(upload_complete(State(state.clone()), Path(id), remote()).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn idempotency_keys_are_scoped_per_client() {
+        let state = test_state("idempotency");
+        let mut headers = HeaderMap::new();
+        headers.insert("idempotency-key", HeaderValue::from_static("retry-1"));
+        let other = ConnectInfo(SocketAddr::from(([127, 0, 0, 2], 40000)));
+        let mut names = Vec::new();
+        for from in [remote(), remote(), other] {
+            let (content_type, body) = multipart_body(&[("a.txt", b"hello")]);
+            let resp = upload_with(&state, from, headers.clone(), &content_type, body).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let replayed = resp.headers().contains_key("idempotent-replayed");
+            names.push((body_json(resp).await["name"].as_str().unwrap().to_string(), replayed));
+        }
+        // 同一客户端重试得到原结果；另一个客户端的同名 key 是一次新的上传
+        assert_eq!(names[1], (names[0].0.clone(), true));
+        assert!(!names[2].1);
+        assert_ne!(names[2].0, names[0].0);
+    }
+
+    #[tokio::test]
+    async fn conditional_writes() {
+        let state = test_state("conditional");
+        let path = state.save_path.join("cond1");
+
+        // If-None-Match: * 只在 note 不存在时创建
+        assert_eq!(post(&state, "cond1", &[("if-none-match", "*")], "v1").await.status(), StatusCode::OK);
+        let resp = post(&state, "cond1", &[("if-none-match", "*")], "v2").await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "v1");
+
+        // If-Match 需要当前内容的 ETag
+        let etag = content_etag(b"v1");
+        let resp = post(&state, "cond1", &[("if-match", "\"0000000000000000\"")], "v2").await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(post(&state, "cond1", &[("if-match", &etag)], "v2").await.status(), StatusCode::OK);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "v2");
+        // 旧 ETag 已失效
+        let resp = post(&state, "cond1", &[("if-match", &etag)], "v3").await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+
+        // 不存在的 note 不匹配 If-Match: *
+        let resp = post(&state, "cond2", &[("if-match", "*")], "v1").await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+        assert!(!state.save_path.join("cond2").exists());
+    }
+
+    #[tokio::test]
+    async fn alias_and_rename_reject_reserved_ids() {
+        let state = test_state("reserved-ids");
+        fs::write(state.save_path.join("abcde"), "hello").unwrap();
+        for id in RESERVED_NOTE_IDS {
+            let form = AliasForm { alias: id.to_string() };
+            let resp = create_alias(State(state.clone()), Path("abcde".to_string()), Form(form)).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "alias {id}");
+            let form = RenameForm { to: id.to_string() };
+            let resp = rename_note(State(state.clone()), Path("abcde".to_string()), remote(), Form(form)).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "rename {id}");
+            assert!(!state.save_path.join(id).exists());
+        }
+        assert!(state.save_path.join("abcde").is_file());
+    }
+
+    #[tokio::test]
+    async fn rename_is_audited_and_keeps_access_time() {
+        let mut state = test_state("rename-audit");
+        let log_path = env::temp_dir().join(format!("wmn-audit-rename-{}.log", std::process::id()));
+        let _ = fs::remove_file(&log_path);
+        state.audit_log = Some(audit::AuditLog::open(&log_path).unwrap());
+        let times = Arc::new(access_times::AccessTimes::load(&state.save_path));
+        state.access_times = Some(times.clone());
+        fs::write(state.save_path.join("from1"), "hello").unwrap();
+        times.touch("from1");
+        let read_at = times.get("from1");
+
+        let form = RenameForm { to: "to1".to_string() };
+        let resp = rename_note(State(state.clone()), Path("from1".to_string()), remote(), Form(form)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(times.get("to1"), read_at);
+        assert_eq!(times.get("from1"), None);
+        let event: serde_json::Value = serde_json::from_str(fs::read_to_string(&log_path).unwrap().trim()).unwrap();
+        assert_eq!(event["event"], "rename");
+        assert_eq!((event["note"].as_str(), event["to"].as_str()), (Some("from1"), Some("to1")));
+        assert_eq!((event["ip"].as_str(), event["bytes"].as_u64()), (Some("127.0.0.1"), Some(5)));
+        let _ = fs::remove_file(&log_path);
+    }
+
+    #[tokio::test]
+    async fn alias_and_rename_reject_short_ids() {
+        let mut state = test_state("short-ids");
+        state.reject_short_ids = true;
+        fs::write(state.save_path.join("abcde"), "hello").unwrap();
+        let form = AliasForm { alias: "ab".to_string() };
+        let resp = create_alias(State(state.clone()), Path("abcde".to_string()), Form(form)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(body_json(resp).await["error"], "note_id_too_short");
+        let form = RenameForm { to: "ab".to_string() };
+        let resp = rename_note(State(state.clone()), Path("abcde".to_string()), remote(), Form(form)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(body_json(resp).await["min_length"], 5);
+        assert!(state.save_path.join("abcde").is_file());
+        assert!(!state.save_path.join("ab").exists());
+
+        let form = RenameForm { to: "abcdef".to_string() };
+        let resp = rename_note(State(state.clone()), Path("abcde".to_string()), remote(), Form(form)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn embed_honours_noindex() {
+        let state = test_state("embed-noindex");
+        fs::write(state.save_path.join("emb1"), "hello").unwrap();
+        let resp = embed_note(State(state.clone()), Path("emb1".to_string()), remote()).await;
+        assert!(resp.headers().get("x-robots-tag").is_none());
+
+        meta::save(&state.save_path, "emb1", &meta::NoteMeta { noindex: true, ..Default::default() }).unwrap();
+        let resp = embed_note(State(state.clone()), Path("emb1".to_string()), remote()).await;
+        assert_eq!(resp.headers().get("x-robots-tag").unwrap(), "noindex");
+        let html = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&html).contains("<meta name=\"robots\" content=\"noindex\">"));
+    }
+
+    #[tokio::test]
+    async fn upload_keeps_stored_files_when_a_later_field_fails() {
+        let state = test_state("upload-mixed");
+        // 第二个文件超出默认的请求体上限（2MB），读取失败；第一个文件已经保存
+        let big = vec![b'x'; 3 * 1024 * 1024];
+        let (content_type, body) = multipart_body(&[("a.txt", b"hello"), ("big.bin", &big)]);
+        let resp = upload(&state, &content_type, body).await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+        let json = body_json(resp).await;
+        let results = json["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["ok"], true);
+        assert_eq!(results[1]["ok"], false);
+        assert_eq!(results[1]["error"], "file_too_large");
+        let stored = results[0]["name"].as_str().unwrap();
+        assert_eq!(fs::read(state.save_path.join(stored)).unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn upload_reports_each_file_of_a_mixed_batch() {
+        let state = test_state("upload-batch");
+        let (content_type, body) = multipart_body(&[("a.txt", b"one"), ("b.txt", b"two")]);
+        // 只剩一个名额：第一个文件成功，第二个因 FILE_LIMIT 失败
+        let state = AppState { file_limit: 1, ..state };
+        let resp = upload(&state, &content_type, body).await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+        let json = body_json(resp).await;
+        assert_eq!(json["results"][0]["ok"], true);
+        assert_eq!(json["results"][1]["ok"], false);
+        assert_eq!(json["results"][1]["error"], "file_limit_reached");
+    }
+}
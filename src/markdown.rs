@@ -0,0 +1,75 @@
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+// 服务端 markdown 渲染，供只读页面等无需编辑器 JS 的场景使用。
+// note 内容不可信：原始 HTML 一律按文本输出，危险协议的链接/图片地址替换为 "#"
+pub fn render_markdown(text: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+
+    let parser = Parser::new_ext(text, options).map(|event| match event {
+        Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+        Event::Start(Tag::Link { link_type, dest_url, title, id }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image { link_type, dest_url, title, id }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        other => other,
+    });
+
+    let mut out = String::with_capacity(text.len() * 3 / 2);
+    html::push_html(&mut out, parser);
+    out
+}
+
+// 按语言提示渲染：markdown（默认）走 render_markdown，其余语言输出带 language-* class 的代码块
+pub fn render_note(text: &str, lang: Option<&str>) -> String {
+    match lang {
+        None => render_markdown(text),
+        Some(lang) => format!(
+            "<pre><code class=\"language-{lang}\">{}</code></pre>",
+            crate::html_escape(text)
+        ),
+    }
+}
+
+// 粗略判断内容是否为 markdown：标题、代码块、表格各计 2 分，列表、引用、链接各计 1 分，
+// 只看前 200 行，累计达到 2 分即认为是 markdown
+pub fn looks_like_markdown(text: &str) -> bool {
+    let mut score = 0;
+    for line in text.lines().take(200) {
+        let line = line.trim_start();
+        let hashes = line.bytes().take_while(|&b| b == b'#').count();
+        let heading = (1..=6).contains(&hashes) && line[hashes..].starts_with(' ');
+        let fence = line.starts_with("```") || line.starts_with("~~~");
+        let table = line.starts_with('|') && line.contains("---");
+        let list = ["- ", "* ", "+ ", "> "].iter().any(|p| line.starts_with(p))
+            || line.split_once(". ").is_some_and(|(n, _)| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+        let link = line.contains('[') && line.contains("](");
+        if heading || fence || table {
+            score += 2;
+        } else if list || link {
+            score += 1;
+        }
+        if score >= 2 {
+            return true;
+        }
+    }
+    false
+}
+
+fn safe_url(url: CowStr) -> CowStr {
+    let lower = url.trim().to_ascii_lowercase();
+    if lower.starts_with("javascript:") || lower.starts_with("vbscript:") || lower.starts_with("data:") {
+        return CowStr::Borrowed("#");
+    }
+    url
+}
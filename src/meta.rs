@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// note 的附加信息，以 JSON 形式保存在 save_path/.meta/<note>.json。
+// 目录以 "." 开头，不会与 note ID 冲突，也不计入文件数量
+pub const META_DIR: &str = ".meta";
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct NoteMeta {
+    // 非空表示该 ID 是别名，指向真实的 note
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias_of: Option<String>,
+    // 管理员为该 note 单独设置的大小上限（字节），优先于全局 SINGLE_FILE_SIZE_LIMIT
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_limit: Option<usize>,
+    // 内容类型/语言提示（如 json、rust），为空表示默认的 markdown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    // 冻结的 note 拒绝写入与删除
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub immutable: bool,
+    // 页面输出 noindex，禁止搜索引擎收录
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub noindex: bool,
+    // VERIFY_CONTENT_HASH 开启时写入的内容哈希（与 ETag 相同），读取时用来发现文件被损坏或改动
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    // 作者设置的页面描述，用于 meta description 与 OpenGraph；为空时使用自动摘要
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+// 规范化语言提示：markdown 视为默认值返回 Ok(None)
+pub fn normalize_lang(lang: &str) -> Result<Option<String>, &'static str> {
+    let lang = lang.trim().to_ascii_lowercase();
+    if lang.is_empty() || lang == "markdown" || lang == "md" {
+        return Ok(None);
+    }
+    if lang.len() > 32 || !lang.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-')) {
+        return Err("invalid lang: expected up to 32 characters from [a-z0-9_+-]");
+    }
+    Ok(Some(lang))
+}
+
+// raw 输出时按语言提示选择的 Content-Type；其余语言一律按纯文本输出，
+// 不会以 text/html 等可执行类型返回 note 内容
+pub fn lang_content_type(lang: Option<&str>) -> &'static str {
+    match lang {
+        Some("json") => "application/json; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        _ => "text/plain; charset=utf-8",
+    }
+}
+
+fn meta_path(save_path: &Path, note: &str) -> PathBuf {
+    save_path.join(META_DIR).join(format!("{note}.json"))
+}
+
+// 文件不存在或内容损坏时返回默认值
+pub fn load(save_path: &Path, note: &str) -> NoteMeta {
+    fs::read(meta_path(save_path, note))
+        .ok()
+        .and_then(|b| serde_json::from_slice(&b).ok())
+        .unwrap_or_default()
+}
+
+// 全部为默认值时删除 sidecar，避免留下空文件
+pub fn save(save_path: &Path, note: &str, meta: &NoteMeta) -> io::Result<()> {
+    let path = meta_path(save_path, note);
+    if *meta == NoteMeta::default() {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    fs::create_dir_all(save_path.join(META_DIR))?;
+    fs::write(path, serde_json::to_vec(meta)?)
+}
+
+// 指向 note 的所有别名
+pub fn aliases_of(save_path: &Path, note: &str) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(save_path.join(META_DIR)) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.strip_suffix(".json")?.to_string();
+            (load(save_path, &name).alias_of.as_deref() == Some(note)).then_some(name)
+        })
+        .collect()
+}
+
+// 上传文件探测到的 Content-Type，保存在 .meta/_tmp/<文件名>
+fn upload_type_path(save_path: &Path, stored: &str) -> PathBuf {
+    save_path.join(META_DIR).join("_tmp").join(stored)
+}
+
+pub fn save_upload_type(save_path: &Path, stored: &str, mime: &str) -> io::Result<()> {
+    let path = upload_type_path(save_path, stored);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, mime)
+}
+
+pub fn remove_upload_type(save_path: &Path, stored: &str) {
+    let _ = fs::remove_file(upload_type_path(save_path, stored));
+}
+
+pub fn load_upload_type(save_path: &Path, stored: &str) -> Option<String> {
+    fs::read_to_string(upload_type_path(save_path, stored))
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
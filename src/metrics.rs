@@ -0,0 +1,97 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// 写入/上传大小直方图的默认分桶上界（字节）：1K 到 16M，每档 4 倍
+pub const DEFAULT_SIZE_BUCKETS: &[u64] = &[1024, 4096, 16384, 65536, 262144, 1048576, 4194304, 16777216];
+
+// 进程内计数器，以 Prometheus 文本格式在 /metrics 输出
+pub struct Metrics {
+    pub note_id_collisions: AtomicU64,
+    pub file_limit_reached: AtomicU64,
+    pub note_write_bytes: Histogram,
+    pub upload_bytes: Histogram,
+}
+
+// 固定分桶的直方图；各桶只记本档数量，输出时再累加成 Prometheus 要求的累计值
+pub struct Histogram {
+    bounds: Vec<u64>,
+    // 比 bounds 多一个，最后一个对应 +Inf
+    buckets: Vec<AtomicU64>,
+    sum: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new(size_buckets: &[u64]) -> Self {
+        Metrics {
+            note_id_collisions: AtomicU64::new(0),
+            file_limit_reached: AtomicU64::new(0),
+            note_write_bytes: Histogram::new(size_buckets),
+            upload_bytes: Histogram::new(size_buckets),
+        }
+    }
+
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "note_id_collisions_total",
+            "Random note IDs that collided with an existing note",
+            &self.note_id_collisions,
+        );
+        counter(
+            &mut out,
+            "file_limit_reached_total",
+            "Writes rejected because FILE_LIMIT was reached",
+            &self.file_limit_reached,
+        );
+        self.note_write_bytes.render(&mut out, "note_write_bytes", "Size of saved note contents in bytes");
+        self.upload_bytes.render(&mut out, "upload_bytes", "Size of stored uploaded files in bytes");
+        out
+    }
+}
+
+impl Histogram {
+    // bounds 需为升序
+    pub fn new(bounds: &[u64]) -> Self {
+        Histogram {
+            bounds: bounds.to_vec(),
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        let i = self.bounds.partition_point(|&b| b < value);
+        self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let mut total = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            total += bucket.load(Ordering::Relaxed);
+            match self.bounds.get(i) {
+                Some(bound) => {
+                    let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {total}");
+                }
+                None => {
+                    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {total}");
+                }
+            }
+        }
+        let _ = writeln!(out, "{name}_sum {}", self.sum.load(Ordering::Relaxed));
+        let _ = writeln!(out, "{name}_count {total}");
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+}
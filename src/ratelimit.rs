@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, ReadBuf};
+
+// 按客户端 IP 的固定窗口计数限流
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    clients: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> RateLimiter {
+        RateLimiter {
+            limit,
+            window,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    // 允许时返回 Ok；超出时返回距窗口结束的秒数，用于 Retry-After
+    pub fn check(&self, ip: IpAddr) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        // 条目过多时清掉已过期的窗口，避免内存无限增长
+        if clients.len() > 10_000 {
+            clients.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+        let entry = clients.entry(ip).or_insert((now, 0));
+        if now.duration_since(entry.0) >= self.window {
+            *entry = (now, 0);
+        }
+        if entry.1 >= self.limit {
+            let remaining = self.window.saturating_sub(now.duration_since(entry.0));
+            return Err(remaining.as_secs().max(1));
+        }
+        entry.1 += 1;
+        Ok(())
+    }
+}
+
+// 按客户端 IP 限制同时进行中的请求数；返回的 ConcurrencySlot 释放（drop）时归还名额
+pub struct ConcurrencyLimiter {
+    max: usize,
+    active: Mutex<HashMap<IpAddr, usize>>,
+}
+
+pub struct ConcurrencySlot {
+    limiter: Arc<ConcurrencyLimiter>,
+    ip: IpAddr,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(max: usize) -> ConcurrencyLimiter {
+        ConcurrencyLimiter {
+            max,
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    // 名额已满时返回 None
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConcurrencySlot> {
+        let mut active = self.active.lock().unwrap();
+        let count = active.entry(ip).or_insert(0);
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+        Some(ConcurrencySlot { limiter: self.clone(), ip })
+    }
+}
+
+impl Drop for ConcurrencySlot {
+    fn drop(&mut self) {
+        let mut active = self.limiter.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.ip);
+            }
+        }
+    }
+}
+
+// 全局限制同时进行中的文件读取（MAX_FILE_SERVES），与按 IP 的限制无关；
+// 返回的 FileSlot 释放时归还名额
+pub struct FileSlots {
+    max: usize,
+    active: AtomicUsize,
+}
+
+pub struct FileSlot {
+    slots: Arc<FileSlots>,
+}
+
+impl FileSlots {
+    pub fn new(max: usize) -> FileSlots {
+        FileSlots { max, active: AtomicUsize::new(0) }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    // 名额已满时返回 None
+    pub fn acquire(self: &Arc<Self>) -> Option<FileSlot> {
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < self.max).then_some(n + 1))
+            .ok()
+            .map(|_| FileSlot { slots: self.clone() })
+    }
+}
+
+impl Drop for FileSlot {
+    fn drop(&mut self) {
+        self.slots.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// 持有名额的读取器：作为响应体流式发送时，名额在响应发送完毕（或连接中断）、读取器释放时才归还
+pub struct SlotReader<R> {
+    inner: R,
+    _slot: Option<FileSlot>,
+}
+
+impl<R> SlotReader<R> {
+    pub fn new(inner: R, slot: Option<FileSlot>) -> SlotReader<R> {
+        SlotReader { inner, _slot: slot }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for SlotReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
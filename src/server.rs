@@ -0,0 +1,113 @@
+use axum::extract::ConnectInfo;
+use axum::Router;
+use hyper::body::Incoming;
+use hyper::Request;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use socket2::{SockRef, TcpKeepalive};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpSocket};
+use tower::ServiceExt;
+use tracing::{debug, error};
+
+// 监听与连接参数；默认值与之前 axum::serve 的行为一致
+pub struct ServerOptions {
+    pub backlog: u32,
+    pub http_keepalive: bool,
+    pub tcp_keepalive: Option<Duration>,
+    // 读取完整请求头的时限，防止慢速发送请求头长期占用连接；None 表示不限制。
+    // 超时时 hyper 不回写 408，直接关闭连接：这时请求还没解析出来，hyper 也没有提供在超时时写响应的入口
+    pub header_read_timeout: Option<Duration>,
+    // 请求头缓冲上限（字节），HTTP/1 超出时由 hyper 返回 431 并关闭连接；hyper 要求不小于 8192
+    pub max_header_size: usize,
+}
+
+pub fn bind(addr: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(backlog)
+}
+
+// 逐个接受连接并交给 hyper 处理，同时把对端地址以 ConnectInfo 形式注入请求
+pub async fn serve(listener: TcpListener, app: Router, opts: ServerOptions) {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .keep_alive(opts.http_keepalive)
+        .header_read_timeout(opts.header_read_timeout)
+        .max_buf_size(opts.max_header_size.max(8192));
+    builder.http2().max_header_list_size(opts.max_header_size as u32);
+
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                // 多为 fd 耗尽等暂时性错误，稍等后继续
+                error!("accept error: {e}");
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                continue;
+            }
+        };
+        if let Some(time) = opts.tcp_keepalive {
+            if let Err(e) = SockRef::from(&stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(time)) {
+                debug!("set tcp keepalive error: {e}");
+            }
+        }
+
+        let service = app.clone().map_request(move |mut req: Request<Incoming>| {
+            req.extensions_mut().insert(ConnectInfo(remote));
+            req
+        });
+        let builder = builder.clone();
+        tokio::spawn(async move {
+            let service = TowerToHyperService::new(service);
+            if let Err(e) = builder.serve_connection_with_upgrades(TokioIo::new(stream), service).await {
+                debug!("connection {remote} error: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn start(opts: ServerOptions) -> SocketAddr {
+        let listener = bind(SocketAddr::from(([127, 0, 0, 1], 0)), 16).unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Router::new().route("/", axum::routing::get(|| async { "ok" })), opts));
+        addr
+    }
+
+    async fn exchange(addr: SocketAddr, request: &[u8]) -> String {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+        let mut resp = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut resp)).await.unwrap().unwrap();
+        String::from_utf8_lossy(&resp).into_owned()
+    }
+
+    // 请求头超限返回 431；读取请求头超时则不返回任何响应，直接断开
+    #[tokio::test]
+    async fn header_limits() {
+        let addr = start(ServerOptions {
+            backlog: 16,
+            http_keepalive: true,
+            tcp_keepalive: None,
+            header_read_timeout: Some(Duration::from_millis(200)),
+            max_header_size: 8192,
+        })
+        .await;
+        let big = format!("GET / HTTP/1.1\r\nHost: x\r\nX-Big: {}\r\n\r\n", "a".repeat(20000));
+        assert!(exchange(addr, big.as_bytes()).await.starts_with("HTTP/1.1 431"));
+        assert_eq!(exchange(addr, b"GET / HTTP/1.1\r\nHost: x\r\n").await, "");
+        let ok = exchange(addr, b"GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n").await;
+        assert!(ok.starts_with("HTTP/1.1 200"));
+    }
+}
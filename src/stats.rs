@@ -0,0 +1,68 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+// save_path 下文件数量/总大小的缓存计数。
+// 启动时由后台任务扫描得到基线，扫描完成前 ready=false，此时写入不改动计数
+#[derive(Default)]
+pub struct FileStats {
+    ready: AtomicBool,
+    files: AtomicUsize,
+    bytes: AtomicU64,
+}
+
+impl FileStats {
+    pub fn scan(&self, dir: &Path) -> io::Result<()> {
+        let mut files = 0usize;
+        let mut bytes = 0u64;
+        for entry in fs::read_dir(dir)? {
+            let meta = entry?.metadata()?;
+            if meta.is_file() {
+                files += 1;
+                bytes += meta.len();
+            }
+        }
+        self.files.store(files, Ordering::Relaxed);
+        self.bytes.store(bytes, Ordering::Relaxed);
+        self.ready.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    // 基线未就绪时返回 None，调用方自行回退到目录扫描
+    pub fn file_count(&self) -> Option<usize> {
+        self.is_ready().then(|| self.files.load(Ordering::Relaxed))
+    }
+
+    pub fn total_bytes(&self) -> Option<u64> {
+        self.is_ready().then(|| self.bytes.load(Ordering::Relaxed))
+    }
+
+    // old: 覆盖前的文件大小（新建文件为 None）
+    pub fn record_write(&self, old: Option<u64>, new: u64) {
+        if !self.is_ready() {
+            return;
+        }
+        match old {
+            Some(old) => {
+                let _ = self.bytes.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| Some(n.saturating_sub(old)));
+            }
+            None => {
+                self.files.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.bytes.fetch_add(new, Ordering::Relaxed);
+    }
+
+    pub fn record_remove(&self, old: u64) {
+        if !self.is_ready() {
+            return;
+        }
+        let _ = self.files.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| Some(n.saturating_sub(1)));
+        let _ = self.bytes.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| Some(n.saturating_sub(old)));
+    }
+}
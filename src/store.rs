@@ -0,0 +1,83 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// note 在磁盘上可以是明文，也可以是 gzip 压缩（COMPRESS_NOTES）。
+// 按 gzip 魔数识别：合法的 UTF-8 文本不可能以 1f 8b 开头，因此新旧格式可以混存，
+// 开关切换后旧文件照常可读，下次保存时再按当前设置写入
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.starts_with(&GZIP_MAGIC)
+}
+
+// 读取 note 内容（已解压）
+pub fn read_note(path: &Path) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    decode(data)
+}
+
+// 内容（解压后）大小：压缩的 note 从 gzip 尾部的 ISIZE 字段读取，不必解压整个文件
+pub fn content_len(path: &Path) -> io::Result<u64> {
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut magic = [0u8; 2];
+    if len < 18 || file.read_exact(&mut magic).is_err() || !is_compressed(&magic) {
+        return Ok(len);
+    }
+    let mut isize = [0u8; 4];
+    file.seek(SeekFrom::End(-4))?;
+    file.read_exact(&mut isize)?;
+    Ok(u32::from_le_bytes(isize) as u64)
+}
+
+pub fn read_note_string(path: &Path) -> io::Result<String> {
+    String::from_utf8(read_note(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn decode(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !is_compressed(&data) {
+        return Ok(data);
+    }
+    let mut out = Vec::with_capacity(data.len() * 4);
+    GzDecoder::new(data.as_slice()).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+// 写入 note，返回磁盘上的实际字节数（用于文件统计）
+pub fn write_note(path: &Path, content: &[u8], compress: bool) -> io::Result<u64> {
+    let data = encode(content, compress)?;
+    fs::write(path, &data)?;
+    Ok(data.len() as u64)
+}
+
+pub fn encode(content: &[u8], compress: bool) -> io::Result<Vec<u8>> {
+    if !compress || content.is_empty() {
+        return Ok(content.to_vec());
+    }
+    let mut encoder = GzEncoder::new(Vec::with_capacity(content.len() / 2), Compression::default());
+    encoder.write_all(content)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_len_reports_uncompressed_size() {
+        let dir = std::env::temp_dir().join(format!("wmn-store-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let content = "hello ".repeat(1000);
+        for (name, compress) in [("plain", false), ("gzip", true)] {
+            let path = dir.join(name);
+            let on_disk = write_note(&path, content.as_bytes(), compress).unwrap();
+            assert_eq!(on_disk < content.len() as u64, compress);
+            assert_eq!(content_len(&path).unwrap(), content.len() as u64);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,30 @@
+// 离线查看：笔记页面与静态资源采用网络优先，成功时写入缓存，离线时回退到缓存
+var CACHE = 'web-mini-note-v1';
+
+self.addEventListener('install', function (event) {
+    self.skipWaiting();
+});
+
+self.addEventListener('activate', function (event) {
+    event.waitUntil(self.clients.claim());
+});
+
+self.addEventListener('fetch', function (event) {
+    var req = event.request;
+    if (req.method !== 'GET' || new URL(req.url).origin !== self.location.origin) {
+        return;
+    }
+    event.respondWith(
+        fetch(req).then(function (resp) {
+            if (resp.ok) {
+                var copy = resp.clone();
+                caches.open(CACHE).then(function (cache) { cache.put(req, copy); });
+            }
+            return resp;
+        }).catch(function () {
+            return caches.match(req).then(function (cached) {
+                return cached || Response.error();
+            });
+        })
+    );
+});